@@ -27,14 +27,19 @@ extern crate log;
 extern crate oasis_chain;
 extern crate simple_logger;
 
-use std::{io::Read, os::unix::net::UnixStream};
+use std::{env, fs, io::Read, os::unix::net::UnixStream, time::Duration};
 
 use clap::{App, Arg};
 use failure::Fallible;
 use fdlimit::raise_fd_limit;
 use log::{error, info};
 
-use oasis_chain::{util, BLOCK_GAS_LIMIT, MIN_GAS_PRICE_GWEI};
+use oasis_chain::{util, QueueLimits, BLOCK_GAS_LIMIT, MIN_GAS_PRICE_GWEI};
+
+/// Environment variable from which the key manager state passphrase is read,
+/// unless it is read from a file. It is never taken as an argument, which
+/// would expose it in the process list and shell history.
+const KM_PASSPHRASE_ENV: &str = "OASIS_CHAIN_KM_PASSPHRASE";
 
 fn main() -> Fallible<()> {
     // Increase max number of open files.
@@ -42,6 +47,10 @@ fn main() -> Fallible<()> {
 
     let block_gas_limit = BLOCK_GAS_LIMIT.to_string();
     let gas_price = MIN_GAS_PRICE_GWEI.to_string();
+    let queue_limits = QueueLimits::default();
+    let queue_per_sender = queue_limits.per_sender.to_string();
+    let queue_size = queue_limits.total.to_string();
+    let queue_lifetime = queue_limits.lifetime.as_secs().to_string();
 
     let args = App::new("Oasis chain")
         .arg(
@@ -93,6 +102,33 @@ fn main() -> Fallible<()> {
                 .default_value(&block_gas_limit)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("block-interval")
+                .long("block-interval")
+                .help("Seal pending transactions into a block at this interval (in sec). If omitted, a block is mined for each transaction.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("queue-per-sender")
+                .long("queue-per-sender")
+                .help("Max number of future-nonce transactions queued per sender.")
+                .default_value(&queue_per_sender)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("queue-size")
+                .long("queue-size")
+                .help("Max number of future-nonce transactions queued in total.")
+                .default_value(&queue_size)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("queue-lifetime")
+                .long("queue-lifetime")
+                .help("Time after which a queued future-nonce transaction is dropped (in sec).")
+                .default_value(&queue_lifetime)
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("interface")
                 .long("interface")
@@ -100,6 +136,66 @@ fn main() -> Fallible<()> {
                 .default_value("127.0.0.1")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("data-dir")
+                .long("data-dir")
+                .help("Directory in which to persist chain state. If omitted, state is kept in memory.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state-history")
+                .long("state-history")
+                .help("Number of past block states to retain for historical queries. If omitted, all states are retained.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rpc-apis")
+                .long("rpc-apis")
+                .help("Comma-separated list of RPC APIs to expose, e.g. \"safe,evm\" or \"all\".")
+                .default_value("safe")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tracing")
+                .long("tracing")
+                .help("Record transaction traces when mining blocks, to be served by the traces RPC API."),
+        )
+        .arg(
+            Arg::with_name("key-manager-socket")
+                .long("key-manager-socket")
+                .help("Unix socket of a local key manager process. If omitted, a mock key manager is used.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("km-master-secret")
+                .long("km-master-secret")
+                .help("Secret from which the mock key manager derives contract keys, making them the same across runs. If omitted, contract keys are random.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("km-state-file")
+                .long("km-state-file")
+                .help("File in which the mock key manager keeps its keys, encrypted with a passphrase (from --km-passphrase-file or the OASIS_CHAIN_KM_PASSPHRASE environment variable), so that confidential contract storage can be decrypted after a restart.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("km-passphrase-file")
+                .long("km-passphrase-file")
+                .help("File containing the passphrase with which the key manager state file is encrypted. Takes precedence over the OASIS_CHAIN_KM_PASSPHRASE environment variable.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("epoch-interval")
+                .long("epoch-interval")
+                .help("Rotate the ephemeral input keys of confidential contracts every this many blocks. If omitted, transactions are encrypted to the long-term keys of contracts.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("epoch-grace")
+                .long("epoch-grace")
+                .help("Number of blocks at the start of an epoch during which transactions encrypted to the previous epoch's keys are still accepted. Defaults to the epoch interval.")
+                .takes_value(true),
+        )
         // Logging.
         .arg(
             Arg::with_name("v")
@@ -124,6 +220,42 @@ fn main() -> Fallible<()> {
     let pubsub_interval_secs = value_t!(args, "pubsub-interval", u64)?;
     let gas_price = util::gwei_to_wei(value_t!(args, "gas-price", u64)?);
     let block_gas_limit = value_t!(args, "block-gas-limit", usize)?;
+    let block_interval_secs = match args.is_present("block-interval") {
+        true => Some(value_t!(args, "block-interval", u64)?),
+        false => None,
+    };
+    let queue_limits = QueueLimits {
+        per_sender: value_t!(args, "queue-per-sender", usize)?,
+        total: value_t!(args, "queue-size", usize)?,
+        lifetime: Duration::from_secs(value_t!(args, "queue-lifetime", u64)?),
+    };
+    let data_dir = args.value_of("data-dir").map(String::from);
+    let state_history = match args.is_present("state-history") {
+        true => Some(value_t!(args, "state-history", u64)?),
+        false => None,
+    };
+
+    let rpc_apis = value_t!(args, "rpc-apis", String)?;
+    let tracing = args.is_present("tracing");
+    let key_manager_socket = args.value_of("key-manager-socket").map(String::from);
+    let km_master_secret = args.value_of("km-master-secret").map(String::from);
+    let km_state_file = args.value_of("km-state-file").map(String::from);
+    let km_passphrase = match args.value_of("km-passphrase-file") {
+        Some(path) => Some(
+            fs::read_to_string(path)?
+                .trim_end_matches(|c| c == '\n' || c == '\r')
+                .to_string(),
+        ),
+        None => env::var(KM_PASSPHRASE_ENV).ok(),
+    };
+    let epoch_interval = match args.is_present("epoch-interval") {
+        true => Some(value_t!(args, "epoch-interval", u64)?),
+        false => None,
+    };
+    let epoch_grace = match args.is_present("epoch-grace") {
+        true => Some(value_t!(args, "epoch-grace", u64)?),
+        false => None,
+    };
 
     let chain_info = include_str!("../resources/info.txt");
     info!("Starting Oasis local chain\n{}", chain_info);
@@ -138,6 +270,18 @@ fn main() -> Fallible<()> {
         ws_max_connections,
         gas_price,
         block_gas_limit.into(),
+        block_interval_secs,
+        queue_limits,
+        data_dir.as_ref().map(String::as_str),
+        state_history,
+        &rpc_apis,
+        tracing,
+        key_manager_socket.as_ref().map(String::as_str),
+        km_master_secret.as_ref().map(String::as_str),
+        km_state_file.as_ref().map(String::as_str),
+        km_passphrase.as_ref().map(String::as_str),
+        epoch_interval,
+        epoch_grace,
     );
 
     let client = match client {
@@ -0,0 +1,100 @@
+//! Key derivation.
+use hmac::{Hmac, Mac};
+use sha2::Sha512Trunc256;
+use zeroize::Zeroize;
+
+type Kdf = Hmac<Sha512Trunc256>;
+
+/// Size of a derived key in bytes.
+pub const KEY_SIZE: usize = 32;
+
+/// Derives a key from a secret via HKDF-SHA512/256 (RFC 5869), expanding a
+/// single block of output.
+///
+/// The context is used as the salt and separates the keys derived for
+/// different purposes; the info binds the key to what it is used for (e.g.,
+/// a contract identifier).
+pub fn derive_key(secret: &[u8], context: &[u8], info: &[u8]) -> [u8; KEY_SIZE] {
+    // Extract.
+    let mut kdf = Kdf::new_varkey(context).expect("Hmac::new_varkey");
+    kdf.input(secret);
+    let mut prk = [0u8; KEY_SIZE];
+    prk.copy_from_slice(&kdf.result().code().as_ref()[..KEY_SIZE]);
+
+    // Expand.
+    let mut kdf = Kdf::new_varkey(&prk).expect("Hmac::new_varkey");
+    prk.zeroize();
+    kdf.input(info);
+    kdf.input(&[0x01]);
+    let mut key = [0u8; KEY_SIZE];
+    key.copy_from_slice(&kdf.result().code().as_ref()[..KEY_SIZE]);
+
+    key
+}
+
+/// Derives a key from a passphrase via PBKDF2-HMAC-SHA512/256 (RFC 8018),
+/// computing a single block of output.
+pub fn derive_key_from_passphrase(
+    passphrase: &[u8],
+    salt: &[u8],
+    iterations: u32,
+) -> [u8; KEY_SIZE] {
+    assert!(iterations > 0, "at least one iteration is required");
+
+    let mut kdf = Kdf::new_varkey(passphrase).expect("Hmac::new_varkey");
+    kdf.input(salt);
+    kdf.input(&1u32.to_be_bytes());
+    let mut block = [0u8; KEY_SIZE];
+    block.copy_from_slice(&kdf.result().code().as_ref()[..KEY_SIZE]);
+
+    let mut key = block;
+    for _ in 1..iterations {
+        let mut kdf = Kdf::new_varkey(passphrase).expect("Hmac::new_varkey");
+        kdf.input(&block);
+        block.copy_from_slice(&kdf.result().code().as_ref()[..KEY_SIZE]);
+        for (k, b) in key.iter_mut().zip(block.iter()) {
+            *k ^= b;
+        }
+    }
+    block.zeroize();
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_key() {
+        let key = derive_key(b"master secret", b"test context", b"info");
+        assert_eq!(
+            key.to_vec(),
+            vec![
+                0x90, 0x4b, 0x7d, 0xa1, 0xdd, 0xb0, 0x29, 0x63, 0x46, 0x31, 0x24, 0x3d, 0x5f, 0x48,
+                0x0b, 0x1c, 0x48, 0xca, 0x2a, 0xf5, 0x90, 0xbe, 0xa1, 0x45, 0x50, 0x4f, 0x5a, 0xa4,
+                0xce, 0xe3, 0xc6, 0x7e,
+            ]
+        );
+
+        assert_ne!(derive_key(b"master secret", b"other context", b"info"), key);
+        assert_ne!(derive_key(b"master secret", b"test context", b"other"), key);
+        assert_ne!(derive_key(b"other secret", b"test context", b"info"), key);
+    }
+
+    #[test]
+    fn test_derive_key_from_passphrase() {
+        let key = derive_key_from_passphrase(b"passphrase", b"salt", 10);
+        assert_eq!(
+            key.to_vec(),
+            vec![
+                0x3c, 0x5c, 0xe5, 0x5d, 0x9c, 0x42, 0xa1, 0x0a, 0x65, 0x08, 0xdd, 0x90, 0x07, 0x3a,
+                0x33, 0x86, 0x7a, 0x09, 0xef, 0x07, 0x6b, 0xbc, 0x10, 0x6f, 0x9b, 0x36, 0x12, 0x90,
+                0xaf, 0x46, 0x39, 0xd1,
+            ]
+        );
+
+        assert_ne!(derive_key_from_passphrase(b"passphrase", b"salt", 11), key);
+        assert_ne!(derive_key_from_passphrase(b"passphrase", b"other", 10), key);
+    }
+}
@@ -6,6 +6,7 @@ extern crate byteorder;
 extern crate ed25519_dalek;
 #[macro_use]
 extern crate failure;
+extern crate hmac;
 extern crate rand;
 extern crate rustc_hex;
 extern crate serde;
@@ -17,5 +18,6 @@ extern crate zeroize;
 #[macro_use]
 pub mod bytes;
 pub mod hash;
+pub mod kdf;
 pub mod mrae;
 pub mod signature;
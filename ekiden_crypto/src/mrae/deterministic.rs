@@ -0,0 +1,90 @@
+//! Deterministic authenticated encryption via Deoxys-II-256-128.
+//!
+//! Some values, e.g., contract storage keys, must always encrypt to the same
+//! ciphertext so that they can be looked up by it. Deoxys-II is nonce-misuse
+//! resistant: encrypting under a repeated nonce reveals only whether the
+//! (additional data, plaintext) pairs are equal. With a fixed nonce it is
+//! thus a deterministic authenticated encryption scheme, like SIV, with the
+//! tag acting as the synthetic IV. Ciphertexts are `TAG_SIZE` bytes longer
+//! than plaintexts.
+//!
+//! The fixed nonce must never be used for randomized encryption under the
+//! same key, so the cipher key is derived from the caller's key with the
+//! context `DETERMINISTIC_CONTEXT`, and must only be used through this
+//! module.
+
+use failure::Fallible;
+use zeroize::Zeroize;
+
+use super::deoxysii::{DeoxysII, KEY_SIZE, NONCE_SIZE};
+use crate::kdf;
+
+/// Context with which the cipher key is derived from the caller's key.
+pub const DETERMINISTIC_CONTEXT: &[u8] = b"ekiden/mrae/deterministic";
+
+/// Nonce used for every encryption.
+const NONCE: [u8; NONCE_SIZE] = [0u8; NONCE_SIZE];
+
+/// Deterministic authenticated cipher.
+pub struct DeterministicCipher {
+    d2: DeoxysII,
+}
+
+impl DeterministicCipher {
+    /// Create a cipher whose key is derived from the given key.
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        let mut derived_key = kdf::derive_key(key, DETERMINISTIC_CONTEXT, &[]);
+        let d2 = DeoxysII::new(&derived_key);
+        derived_key.zeroize();
+
+        Self { d2 }
+    }
+
+    /// Encrypts and authenticates the plaintext, and authenticates the
+    /// additional data. Equal inputs always give equal ciphertexts.
+    pub fn seal(&self, plaintext: Vec<u8>, additional_data: Vec<u8>) -> Vec<u8> {
+        self.d2.seal(&NONCE, plaintext, additional_data)
+    }
+
+    /// Decrypts and authenticates the ciphertext, and authenticates the
+    /// additional data, which must match the value passed to `seal`.
+    pub fn open(&self, ciphertext: Vec<u8>, additional_data: Vec<u8>) -> Fallible<Vec<u8>> {
+        self.d2
+            .open(&NONCE, ciphertext, additional_data)
+            .map_err(|err| err.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::deoxysii::TAG_SIZE, *};
+
+    #[test]
+    fn test_deterministic_cipher() {
+        let cipher = DeterministicCipher::new(&[1u8; KEY_SIZE]);
+        let plaintext = b"storage key".to_vec();
+
+        // Encryption is deterministic, and expands by a tag.
+        let ciphertext = cipher.seal(plaintext.clone(), vec![]);
+        assert_eq!(ciphertext, cipher.seal(plaintext.clone(), vec![]));
+        assert_eq!(ciphertext.len(), plaintext.len() + TAG_SIZE);
+        assert_ne!(ciphertext, cipher.seal(b"storage kez".to_vec(), vec![]));
+        assert_ne!(ciphertext, cipher.seal(plaintext.clone(), vec![1]));
+        assert_ne!(
+            ciphertext,
+            DeterministicCipher::new(&[2u8; KEY_SIZE]).seal(plaintext.clone(), vec![])
+        );
+
+        // The cipher key is separated from the caller's key.
+        assert_ne!(
+            ciphertext,
+            DeoxysII::new(&[1u8; KEY_SIZE]).seal(&NONCE, plaintext.clone(), vec![])
+        );
+
+        assert_eq!(cipher.open(ciphertext.clone(), vec![]).unwrap(), plaintext);
+        assert!(cipher.open(ciphertext.clone(), vec![1]).is_err());
+        let mut tampered = ciphertext;
+        tampered[0] ^= 1;
+        assert!(cipher.open(tampered, vec![]).is_err());
+    }
+}
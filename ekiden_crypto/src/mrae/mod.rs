@@ -5,4 +5,5 @@ extern crate sha2;
 extern crate x25519_dalek;
 
 pub mod deoxysii;
+pub mod deterministic;
 pub mod nonce;
@@ -3,13 +3,22 @@
 use rand::{rngs::OsRng, Rng};
 use serde_derive::{Deserialize, Serialize};
 
-use ekiden_crypto::{impl_bytes, signature::Signature};
+use ekiden_crypto::{
+    impl_bytes, kdf,
+    signature::{PublicKey as SigningPublicKey, Signature},
+};
 
 impl_bytes!(ContractId, 32, "A 256-bit contract identifier.");
 impl_bytes!(PrivateKey, 32, "A private key.");
 impl_bytes!(PublicKey, 32, "A public key.");
 impl_bytes!(StateKey, 32, "A state key.");
 
+/// Key manager epoch, in which a contract's ephemeral keys are valid.
+pub type Epoch = u64;
+
+/// Signature context of public keys signed by the key manager.
+pub const PUBLIC_KEY_CONTEXT: &[u8] = b"EkKmPubK";
+
 /// Keys for a contract.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ContractKey {
@@ -40,6 +49,50 @@ impl ContractKey {
         )
     }
 
+    /// Derive the keys of a contract from a master secret.
+    ///
+    /// The same master secret and contract always give the same keys.
+    pub fn derive_mock(master_secret: &[u8], contract_id: &ContractId) -> Self {
+        let sk = x25519_dalek::StaticSecret::from(kdf::derive_key(
+            master_secret,
+            b"oasis-chain/km/input-key",
+            contract_id.as_ref(),
+        ));
+        let pk = x25519_dalek::PublicKey::from(&sk);
+
+        let state_key = StateKey(kdf::derive_key(
+            master_secret,
+            b"oasis-chain/km/state-key",
+            contract_id.as_ref(),
+        ));
+
+        ContractKey::new(
+            PublicKey(*pk.as_bytes()),
+            PrivateKey(sk.to_bytes()),
+            state_key,
+            vec![],
+        )
+    }
+
+    /// Derive the ephemeral input key pair of a contract for an epoch from a
+    /// master secret.
+    pub fn derive_ephemeral_mock(
+        master_secret: &[u8],
+        contract_id: &ContractId,
+        epoch: Epoch,
+    ) -> InputKeyPair {
+        let mut info = contract_id.as_ref().to_vec();
+        info.extend_from_slice(&epoch.to_be_bytes());
+        let sk = x25519_dalek::StaticSecret::from(kdf::derive_key(
+            master_secret,
+            b"oasis-chain/km/ephemeral-input-key",
+            &info,
+        ));
+        let pk = x25519_dalek::PublicKey::from(&sk);
+
+        InputKeyPair::new(PublicKey(*pk.as_bytes()), PrivateKey(sk.to_bytes()))
+    }
+
     /// Create a set of `ContractKey`.
     pub fn new(pk: PublicKey, sk: PrivateKey, k: StateKey, sum: Vec<u8>) -> Self {
         Self {
@@ -92,6 +145,33 @@ pub struct SignedPublicKey {
     /// Checksum of the key manager state.
     #[serde(with = "serde_bytes")]
     pub checksum: Vec<u8>,
-    /// Sign(sk, (key || checksum)) from the key manager.
+    /// Epoch of an ephemeral key, or none for a long-term key.
+    #[serde(default)]
+    pub epoch: Option<Epoch>,
+    /// Sign(sk, (key || checksum [|| epoch])) from the key manager.
     pub signature: Signature,
 }
+
+impl SignedPublicKey {
+    /// The signed message, i.e., key || checksum, followed by the epoch
+    /// (as a big-endian 64-bit integer) for an ephemeral key.
+    pub fn message(key: &PublicKey, checksum: &[u8], epoch: Option<Epoch>) -> Vec<u8> {
+        let mut message = key.as_ref().to_vec();
+        message.extend_from_slice(checksum);
+        if let Some(epoch) = epoch {
+            message.extend_from_slice(&epoch.to_be_bytes());
+        }
+        message
+    }
+
+    /// Verify the signature under the key manager's signing key.
+    pub fn verify(&self, signer: &SigningPublicKey) -> bool {
+        self.signature
+            .verify(
+                signer,
+                PUBLIC_KEY_CONTEXT,
+                &Self::message(&self.key, &self.checksum, self.epoch),
+            )
+            .is_ok()
+    }
+}
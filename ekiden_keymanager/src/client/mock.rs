@@ -0,0 +1,382 @@
+//! Mock key manager client which stores everything locally.
+
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Result},
+    sync::Mutex,
+};
+
+use ekiden_crypto::{
+    kdf,
+    signature::{PrivateKey as SigningKey, PublicKey as SigningPublicKey, Signer},
+};
+use rand::{rngs::OsRng, Rng};
+
+use super::KeyManagerClient;
+use crate::{
+    api::{
+        ContractId, ContractKey, Epoch, InputKeyPair, PublicKey, SignedPublicKey,
+        PUBLIC_KEY_CONTEXT,
+    },
+    store::{KeyStore, State},
+};
+
+/// Mock key manager client which stores everything locally.
+///
+/// Contract keys, including ephemeral keys, and the key manager's signing key
+/// are derived from a master secret. The checksum of the key manager state is
+/// a keyed hash of the contract keys under the master secret, and changes
+/// whenever keys are created or imported.
+///
+/// If opened with a key store, the master secret and contract keys are kept
+/// in it, so that they survive restarts.
+pub struct MockClient {
+    keys: Mutex<HashMap<ContractId, ContractKey>>,
+    /// Secret from which contract keys are derived.
+    master_secret: Vec<u8>,
+    /// Checksum of the key manager state.
+    checksum: Mutex<Vec<u8>>,
+    /// Key used to sign public keys.
+    signer: SigningKey,
+    /// Store in which keys are kept, if any.
+    store: Option<KeyStore>,
+    /// Held while keys are added, so that saves of the state are ordered
+    /// without holding the lock on the keys.
+    adding: Mutex<()>,
+}
+
+impl MockClient {
+    /// Create a new mock key manager client with a random master secret.
+    pub fn new() -> Self {
+        let mut master_secret = vec![0u8; kdf::KEY_SIZE];
+        OsRng::new().unwrap().fill(&mut master_secret[..]);
+
+        Self::with_master_secret(master_secret)
+    }
+
+    /// Open a mock key manager client whose state is kept in the given key
+    /// store.
+    ///
+    /// If the store is empty, it is initialized with the given master secret
+    /// (or a random one). Otherwise, the stored state is loaded, and must
+    /// have the given master secret, if any.
+    pub fn open(store: KeyStore, master_secret: Option<Vec<u8>>) -> Result<Self> {
+        let mut client = match store.load()? {
+            Some(state) => {
+                if master_secret.map_or(false, |secret| secret != state.master_secret) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "master secret does not match the stored key manager state",
+                    ));
+                }
+
+                let client = Self::with_master_secret(state.master_secret);
+                let keys: HashMap<_, _> = state.keys.into_iter().collect();
+                *client.checksum.lock().unwrap() = checksum(&client.master_secret, &keys);
+                *client.keys.lock().unwrap() = keys;
+                client
+            }
+            None => match master_secret {
+                Some(master_secret) => Self::with_master_secret(master_secret),
+                None => Self::new(),
+            },
+        };
+        client.store = Some(store);
+        let keys = client.keys.lock().unwrap().clone();
+        client.save(&keys)?;
+
+        Ok(client)
+    }
+
+    /// Create a new mock key manager client which derives contract keys from
+    /// the given master secret, so that they are the same across runs.
+    pub fn with_master_secret(master_secret: Vec<u8>) -> Self {
+        let checksum = checksum(&master_secret, &HashMap::new());
+        let signer = kdf::derive_key(&master_secret, b"oasis-chain/km/signer", &[]);
+
+        Self {
+            keys: Mutex::new(HashMap::new()),
+            master_secret,
+            checksum: Mutex::new(checksum),
+            signer: SigningKey::from_bytes(signer.to_vec()),
+            store: None,
+            adding: Mutex::new(()),
+        }
+    }
+
+    /// Checksum of the key manager state.
+    pub fn checksum(&self) -> Vec<u8> {
+        self.checksum.lock().unwrap().clone()
+    }
+
+    /// Add the keys of a contract, replacing any existing keys if `replace`
+    /// is set, and update the checksum. Returns the contract's keys.
+    ///
+    /// The state is saved before the keys are added, so that no keys are
+    /// used unless they have been saved.
+    fn add_keys(
+        &self,
+        contract_id: ContractId,
+        mut key: ContractKey,
+        replace: bool,
+    ) -> Result<ContractKey> {
+        let _adding = self.adding.lock().unwrap();
+        let mut keys = self.keys.lock().unwrap().clone();
+        if let Some(existing) = keys.get(&contract_id) {
+            if !replace {
+                return Ok(existing.clone());
+            }
+        }
+
+        keys.insert(contract_id, key.clone());
+        let checksum = checksum(&self.master_secret, &keys);
+        if !replace {
+            // Created keys carry the checksum of the state they were
+            // created in.
+            key.checksum = checksum.clone();
+            keys.insert(contract_id, key.clone());
+        }
+        self.save(&keys)?;
+
+        *self.keys.lock().unwrap() = keys;
+        *self.checksum.lock().unwrap() = checksum;
+        Ok(key)
+    }
+
+    /// Public key with which the key manager signs public keys.
+    pub fn signer_public_key(&self) -> SigningPublicKey {
+        self.signer.public_key()
+    }
+
+    /// Sign a public key.
+    fn sign(&self, key: PublicKey, epoch: Option<Epoch>) -> Result<SignedPublicKey> {
+        let checksum = self.checksum();
+        let signature = self
+            .signer
+            .sign(
+                PUBLIC_KEY_CONTEXT,
+                &SignedPublicKey::message(&key, &checksum, epoch),
+            )
+            .map_err(|err| Error::new(ErrorKind::Other, err.to_string()))?;
+
+        Ok(SignedPublicKey {
+            key,
+            checksum,
+            epoch,
+            signature,
+        })
+    }
+
+    /// Save the state to the key store, if any.
+    fn save(&self, keys: &HashMap<ContractId, ContractKey>) -> Result<()> {
+        match self.store {
+            Some(ref store) => store.save(&State {
+                master_secret: self.master_secret.clone(),
+                keys: keys
+                    .iter()
+                    .map(|(contract_id, key)| (*contract_id, key.clone()))
+                    .collect(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl KeyManagerClient for MockClient {
+    fn get_or_create_keys(&self, contract_id: ContractId) -> Result<ContractKey> {
+        if let Some(key) = self.keys.lock().unwrap().get(&contract_id) {
+            return Ok(key.clone());
+        }
+
+        let key = ContractKey::derive_mock(&self.master_secret, &contract_id);
+        self.add_keys(contract_id, key, false)
+    }
+
+    fn get_public_key(&self, contract_id: ContractId) -> Result<Option<SignedPublicKey>> {
+        let key = self.get_or_create_keys(contract_id)?.input_keypair.get_pk();
+        Ok(Some(self.sign(key, None)?))
+    }
+
+    fn get_or_create_ephemeral_keys(
+        &self,
+        contract_id: ContractId,
+        epoch: Epoch,
+    ) -> Result<InputKeyPair> {
+        Ok(ContractKey::derive_ephemeral_mock(
+            &self.master_secret,
+            &contract_id,
+            epoch,
+        ))
+    }
+
+    fn get_ephemeral_public_key(
+        &self,
+        contract_id: ContractId,
+        epoch: Epoch,
+    ) -> Result<Option<SignedPublicKey>> {
+        let key = self
+            .get_or_create_ephemeral_keys(contract_id, epoch)?
+            .get_pk();
+        Ok(Some(self.sign(key, Some(epoch))?))
+    }
+
+    fn get_signer_public_key(&self) -> Result<SigningPublicKey> {
+        Ok(self.signer_public_key())
+    }
+
+    fn import_keys(&self, contract_id: ContractId, key: ContractKey) -> Result<()> {
+        self.add_keys(contract_id, key, true).map(|_| ())
+    }
+}
+
+/// Checksum of the key manager state: a hash of the contract keys, sorted by
+/// contract id, keyed with the master secret.
+fn checksum(master_secret: &[u8], keys: &HashMap<ContractId, ContractKey>) -> Vec<u8> {
+    let mut keys: Vec<_> = keys.iter().collect();
+    keys.sort_by_key(|(contract_id, _)| *contract_id);
+
+    let mut data = vec![];
+    for (contract_id, key) in keys {
+        data.extend_from_slice(contract_id.as_ref());
+        data.extend_from_slice(key.input_keypair.get_pk().as_ref());
+        data.extend_from_slice(key.input_keypair.get_sk().as_ref());
+        data.extend_from_slice(key.state_key.as_ref());
+    }
+
+    kdf::derive_key(master_secret, b"oasis-chain/km/checksum", &data).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_master_secret() {
+        let contract_id = ContractId([1; 32]);
+        let key = MockClient::with_master_secret(b"secret".to_vec())
+            .get_or_create_keys(contract_id)
+            .unwrap();
+
+        let same = MockClient::with_master_secret(b"secret".to_vec())
+            .get_or_create_keys(contract_id)
+            .unwrap();
+        assert_eq!(key.input_keypair.get_pk(), same.input_keypair.get_pk());
+        assert_eq!(key.input_keypair.get_sk(), same.input_keypair.get_sk());
+        assert_eq!(key.state_key, same.state_key);
+
+        let other = MockClient::with_master_secret(b"other".to_vec())
+            .get_or_create_keys(contract_id)
+            .unwrap();
+        assert_ne!(key.input_keypair.get_pk(), other.input_keypair.get_pk());
+        assert_ne!(key.state_key, other.state_key);
+
+        let other = MockClient::with_master_secret(b"secret".to_vec())
+            .get_or_create_keys(ContractId([2; 32]))
+            .unwrap();
+        assert_ne!(key.input_keypair.get_pk(), other.input_keypair.get_pk());
+        assert_ne!(key.state_key, other.state_key);
+    }
+
+    #[test]
+    fn test_signed_public_key() {
+        let client = MockClient::new();
+        let signer = client.signer_public_key();
+        let mut signed = client.get_public_key(ContractId([1; 32])).unwrap().unwrap();
+        assert_eq!(signed.checksum.len(), kdf::KEY_SIZE);
+        assert!(signed.verify(&signer));
+
+        // Signed by another key manager.
+        assert!(!signed.verify(&MockClient::new().signer_public_key()));
+
+        // Tampered checksum.
+        signed.checksum[0] ^= 1;
+        assert!(!signed.verify(&signer));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let client = MockClient::with_master_secret(b"secret".to_vec());
+        let empty = client.checksum();
+        assert_eq!(
+            MockClient::with_master_secret(b"secret".to_vec()).checksum(),
+            empty
+        );
+        assert_ne!(
+            MockClient::with_master_secret(b"other".to_vec()).checksum(),
+            empty
+        );
+
+        // Creating keys changes the checksum, which the keys carry.
+        let key = client.get_or_create_keys(ContractId([1; 32])).unwrap();
+        let created = client.checksum();
+        assert_ne!(created, empty);
+        assert_eq!(key.checksum, created);
+
+        // So does importing keys.
+        client
+            .import_keys(ContractId([2; 32]), ContractKey::generate_mock())
+            .unwrap();
+        let imported = client.checksum();
+        assert_ne!(imported, created);
+        let signed = client.get_public_key(ContractId([1; 32])).unwrap().unwrap();
+        assert_eq!(signed.checksum, imported);
+        assert!(signed.verify(&client.signer_public_key()));
+    }
+
+    #[test]
+    fn test_failed_save() {
+        let dir = std::env::temp_dir().join(format!("km-mock-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let store = KeyStore::new(dir.join("state"), "passphrase").with_iterations(10);
+        let client = MockClient::open(store, None).unwrap();
+        let checksum = client.checksum();
+
+        // Keys which cannot be saved are not added.
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(client.get_or_create_keys(ContractId([1; 32])).is_err());
+        assert!(client
+            .import_keys(ContractId([2; 32]), ContractKey::generate_mock())
+            .is_err());
+        assert!(client.keys.lock().unwrap().is_empty());
+        assert_eq!(client.checksum(), checksum);
+    }
+
+    #[test]
+    fn test_ephemeral_keys() {
+        let client = MockClient::new();
+        let signer = client.signer_public_key();
+        let contract_id = ContractId([1; 32]);
+
+        let key = client.get_or_create_ephemeral_keys(contract_id, 1).unwrap();
+        let long_term = client.get_or_create_keys(contract_id).unwrap();
+        assert_ne!(key.get_pk(), long_term.input_keypair.get_pk());
+        assert_eq!(
+            client
+                .get_or_create_ephemeral_keys(contract_id, 1)
+                .unwrap()
+                .get_sk(),
+            key.get_sk()
+        );
+        assert_ne!(
+            client
+                .get_or_create_ephemeral_keys(contract_id, 2)
+                .unwrap()
+                .get_pk(),
+            key.get_pk()
+        );
+
+        let mut signed = client
+            .get_ephemeral_public_key(contract_id, 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(signed.key, key.get_pk());
+        assert_eq!(signed.epoch, Some(1));
+        assert!(signed.verify(&signer));
+
+        // The epoch is signed.
+        signed.epoch = Some(2);
+        assert!(!signed.verify(&signer));
+    }
+}
@@ -0,0 +1,46 @@
+//! Key manager clients.
+
+use std::io::Result;
+
+use ekiden_crypto::signature::PublicKey as SigningPublicKey;
+
+use crate::api::{ContractId, ContractKey, Epoch, InputKeyPair, SignedPublicKey};
+
+mod mock;
+mod socket;
+
+// Re-exports.
+pub use self::{mock::MockClient, socket::SocketClient};
+
+/// Key manager client interface.
+///
+/// Each contract has long-term keys, used to encrypt its state, and an
+/// ephemeral input key pair per epoch, which may be used instead of the
+/// long-term input key pair to encrypt transactions sent to it.
+pub trait KeyManagerClient: Send + Sync {
+    /// Get or create the keys for a contract.
+    fn get_or_create_keys(&self, contract_id: ContractId) -> Result<ContractKey>;
+
+    /// Get the long-term public key of a contract.
+    fn get_public_key(&self, contract_id: ContractId) -> Result<Option<SignedPublicKey>>;
+
+    /// Get or create the ephemeral input key pair of a contract for an epoch.
+    fn get_or_create_ephemeral_keys(
+        &self,
+        contract_id: ContractId,
+        epoch: Epoch,
+    ) -> Result<InputKeyPair>;
+
+    /// Get the ephemeral public key of a contract for an epoch.
+    fn get_ephemeral_public_key(
+        &self,
+        contract_id: ContractId,
+        epoch: Epoch,
+    ) -> Result<Option<SignedPublicKey>>;
+
+    /// Get the public key with which the key manager signs public keys.
+    fn get_signer_public_key(&self) -> Result<SigningPublicKey>;
+
+    /// Replace the keys of a contract (for debugging).
+    fn import_keys(&self, contract_id: ContractId, key: ContractKey) -> Result<()>;
+}
@@ -0,0 +1,229 @@
+//! Key manager client which talks to a local key manager process.
+//!
+//! Requests and responses are JSON objects, one per line, exchanged over a
+//! Unix domain socket. A request names its method and contract, e.g.,
+//!
+//! ```text
+//! {"method":"get_or_create_keys","contract_id":[1,2,...]}
+//! ```
+//!
+//! and is answered with either `{"ok":<result>}` or `{"error":"<message>"}`.
+//! Byte strings are encoded as arrays of numbers.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Error, ErrorKind, Result, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::de::DeserializeOwned;
+use serde_derive::{Deserialize, Serialize};
+
+use ekiden_crypto::signature::PublicKey as SigningPublicKey;
+
+use super::KeyManagerClient;
+use crate::api::{ContractId, ContractKey, Epoch, InputKeyPair, SignedPublicKey};
+
+/// Key manager request.
+#[derive(Serialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum Request {
+    GetOrCreateKeys {
+        contract_id: ContractId,
+    },
+    GetPublicKey {
+        contract_id: ContractId,
+    },
+    GetOrCreateEphemeralKeys {
+        contract_id: ContractId,
+        epoch: Epoch,
+    },
+    GetEphemeralPublicKey {
+        contract_id: ContractId,
+        epoch: Epoch,
+    },
+    GetSignerPublicKey,
+}
+
+/// Key manager response.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response<T> {
+    Ok(T),
+    Error(String),
+}
+
+/// Key manager client which talks to a local key manager process over a
+/// Unix domain socket.
+///
+/// Long-term contract keys never change, so they are cached after the first
+/// request.
+pub struct SocketClient {
+    path: PathBuf,
+    connection: Mutex<Option<BufReader<UnixStream>>>,
+    keys: Mutex<HashMap<ContractId, ContractKey>>,
+}
+
+impl SocketClient {
+    /// Create a new client for the key manager listening on the given socket.
+    ///
+    /// Fails if the key manager cannot be reached.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let stream = UnixStream::connect(&path)?;
+
+        Ok(Self {
+            path,
+            connection: Mutex::new(Some(BufReader::new(stream))),
+            keys: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Send a request and wait for its response.
+    ///
+    /// Reconnects if the connection was dropped by a previous failure.
+    fn call<T: DeserializeOwned>(&self, request: &Request) -> Result<T> {
+        let mut connection = self.connection.lock().unwrap();
+        if connection.is_none() {
+            *connection = Some(BufReader::new(UnixStream::connect(&self.path)?));
+        }
+
+        let result = Self::exchange(connection.as_mut().unwrap(), request);
+        if result.is_err() {
+            // The connection may be out of sync, so drop it.
+            *connection = None;
+        }
+
+        match result? {
+            Response::Ok(value) => Ok(value),
+            Response::Error(message) => Err(Error::new(ErrorKind::Other, message)),
+        }
+    }
+
+    fn exchange<T: DeserializeOwned>(
+        connection: &mut BufReader<UnixStream>,
+        request: &Request,
+    ) -> Result<Response<T>> {
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        connection.get_mut().write_all(&line)?;
+
+        let mut line = String::new();
+        if connection.read_line(&mut line)? == 0 {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "key manager closed the connection",
+            ));
+        }
+
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+impl KeyManagerClient for SocketClient {
+    fn get_or_create_keys(&self, contract_id: ContractId) -> Result<ContractKey> {
+        if let Some(key) = self.keys.lock().unwrap().get(&contract_id) {
+            return Ok(key.clone());
+        }
+
+        let key: ContractKey = self.call(&Request::GetOrCreateKeys { contract_id })?;
+        self.keys.lock().unwrap().insert(contract_id, key.clone());
+
+        Ok(key)
+    }
+
+    fn get_public_key(&self, contract_id: ContractId) -> Result<Option<SignedPublicKey>> {
+        self.call(&Request::GetPublicKey { contract_id })
+    }
+
+    fn get_or_create_ephemeral_keys(
+        &self,
+        contract_id: ContractId,
+        epoch: Epoch,
+    ) -> Result<InputKeyPair> {
+        self.call(&Request::GetOrCreateEphemeralKeys { contract_id, epoch })
+    }
+
+    fn get_ephemeral_public_key(
+        &self,
+        contract_id: ContractId,
+        epoch: Epoch,
+    ) -> Result<Option<SignedPublicKey>> {
+        self.call(&Request::GetEphemeralPublicKey { contract_id, epoch })
+    }
+
+    fn get_signer_public_key(&self) -> Result<SigningPublicKey> {
+        self.call(&Request::GetSignerPublicKey)
+    }
+
+    fn import_keys(&self, _contract_id: ContractId, _key: ContractKey) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::Other,
+            "importing keys into an external key manager is not supported",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixListener, thread};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::api::PublicKey;
+
+    #[test]
+    fn test_socket_client() {
+        let path = std::env::temp_dir().join(format!("km-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+
+        // Stand-in key manager which serves a fixed key and fails anything else.
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut requests = vec![];
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let request: Value = serde_json::from_str(&line).unwrap();
+                let response = match request["method"].as_str().unwrap() {
+                    "get_or_create_keys" => json!({"ok": ContractKey::new(
+                        PublicKey([1; 32]),
+                        Default::default(),
+                        Default::default(),
+                        vec![],
+                    )}),
+                    _ => json!({"error": "not supported"}),
+                };
+                let mut response = serde_json::to_vec(&response).unwrap();
+                response.push(b'\n');
+                reader.get_mut().write_all(&response).unwrap();
+                requests.push(request);
+                line.clear();
+            }
+            requests
+        });
+
+        let client = SocketClient::new(&path).unwrap();
+        let contract_id = ContractId([2; 32]);
+        let key = client.get_or_create_keys(contract_id).unwrap();
+        assert_eq!(key.input_keypair.get_pk(), PublicKey([1; 32]));
+
+        // Keys are cached.
+        client.get_or_create_keys(contract_id).unwrap();
+
+        let err = client.get_public_key(contract_id).unwrap_err();
+        assert_eq!(err.to_string(), "not supported");
+
+        drop(client);
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0]["method"], "get_or_create_keys");
+        assert_eq!(requests[1]["method"], "get_public_key");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -3,10 +3,12 @@ extern crate rand;
 extern crate rustc_hex;
 extern crate serde;
 extern crate serde_derive;
+extern crate serde_json;
 
 #[macro_use]
 mod api;
 pub mod client;
+pub mod store;
 
 // Re-exports.
 pub use api::*;
@@ -0,0 +1,203 @@
+//! Encrypted file storage of key manager state.
+//!
+//! The state is serialized as JSON and sealed with Deoxys-II under a key
+//! derived from a passphrase. The file holds salt || nonce || ciphertext.
+//!
+//! Deriving the key is slow by design, so it is derived once per store and
+//! the salt is kept across saves; each save uses a fresh nonce.
+
+use std::{
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use ekiden_crypto::{
+    kdf,
+    mrae::deoxysii::{DeoxysII, NONCE_SIZE, TAG_SIZE},
+};
+use rand::{rngs::OsRng, Rng};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::api::{ContractId, ContractKey};
+
+/// Size of the passphrase salt in bytes.
+const SALT_SIZE: usize = 16;
+/// Number of PBKDF2 iterations used to derive the sealing key.
+const ITERATIONS: u32 = 100_000;
+/// Additional data authenticated with the sealed state.
+const ADDITIONAL_DATA: &[u8] = b"oasis-chain/km/state";
+
+/// Key manager state.
+#[derive(Serialize, Deserialize)]
+pub struct State {
+    /// Secret from which contract keys are derived.
+    #[serde(with = "serde_bytes")]
+    pub master_secret: Vec<u8>,
+    /// Keys of contracts.
+    pub keys: Vec<(ContractId, ContractKey)>,
+}
+
+/// File in which key manager state is kept, sealed with a passphrase.
+pub struct KeyStore {
+    path: PathBuf,
+    passphrase: Vec<u8>,
+    iterations: u32,
+    /// Salt and sealing key of the file, once loaded or saved.
+    sealing_key: Mutex<Option<([u8; SALT_SIZE], [u8; kdf::KEY_SIZE])>>,
+}
+
+impl KeyStore {
+    /// Create a key store for the given file.
+    pub fn new<P: AsRef<Path>>(path: P, passphrase: &str) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            passphrase: passphrase.as_bytes().to_vec(),
+            iterations: ITERATIONS,
+            sealing_key: Mutex::new(None),
+        }
+    }
+
+    /// Use the given number of PBKDF2 iterations, to keep tests fast.
+    #[cfg(test)]
+    pub(crate) fn with_iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Load the state, if the file exists.
+    ///
+    /// Fails if the file cannot be opened with the passphrase.
+    pub fn load(&self) -> Result<Option<State>> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if data.len() < SALT_SIZE + NONCE_SIZE + TAG_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "truncated key manager state",
+            ));
+        }
+
+        let (salt, data) = data.split_at(SALT_SIZE);
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        let mut salt_array = [0u8; SALT_SIZE];
+        salt_array.copy_from_slice(salt);
+        let mut nonce_array = [0u8; NONCE_SIZE];
+        nonce_array.copy_from_slice(nonce);
+
+        let mut sealing_key = self.sealing_key.lock().unwrap();
+        let key = match *sealing_key {
+            Some((cached_salt, key)) if cached_salt == salt_array => key,
+            _ => self.derive_key(&salt_array),
+        };
+        let plaintext = DeoxysII::new(&key)
+            .open(&nonce_array, ciphertext.to_vec(), ADDITIONAL_DATA.to_vec())
+            .map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "cannot open key manager state, wrong passphrase?",
+                )
+            })?;
+        *sealing_key = Some((salt_array, key));
+
+        Ok(Some(serde_json::from_slice(&plaintext)?))
+    }
+
+    /// Save the state, replacing the file.
+    pub fn save(&self, state: &State) -> Result<()> {
+        let mut rng = OsRng::new()?;
+        let mut nonce = [0u8; NONCE_SIZE];
+        rng.fill(&mut nonce);
+
+        let mut sealing_key = self.sealing_key.lock().unwrap();
+        let (salt, key) = match *sealing_key {
+            Some(cached) => cached,
+            None => {
+                let mut salt = [0u8; SALT_SIZE];
+                rng.fill(&mut salt);
+                (salt, self.derive_key(&salt))
+            }
+        };
+        *sealing_key = Some((salt, key));
+        drop(sealing_key);
+
+        let plaintext = serde_json::to_vec(state)?;
+        let ciphertext = DeoxysII::new(&key).seal(&nonce, plaintext, ADDITIONAL_DATA.to_vec());
+
+        let mut data = salt.to_vec();
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+
+        // Write to a temporary file first, so that the state is never lost
+        // to a partial write.
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    fn derive_key(&self, salt: &[u8]) -> [u8; kdf::KEY_SIZE] {
+        kdf::derive_key_from_passphrase(&self.passphrase, salt, self.iterations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(path: &Path, passphrase: &str) -> KeyStore {
+        KeyStore::new(path, passphrase).with_iterations(10)
+    }
+
+    #[test]
+    fn test_key_store() {
+        let path = std::env::temp_dir().join(format!("km-state-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let store = store(&path, "passphrase");
+        assert!(store.load().unwrap().is_none());
+
+        let contract_id = ContractId([1; 32]);
+        store
+            .save(&State {
+                master_secret: vec![2; 32],
+                keys: vec![(contract_id, ContractKey::generate_mock())],
+            })
+            .unwrap();
+
+        let state = store.load().unwrap().unwrap();
+        assert_eq!(state.master_secret, vec![2; 32]);
+        assert_eq!(state.keys.len(), 1);
+        assert_eq!(state.keys[0].0, contract_id);
+
+        // Later saves keep the salt, and can be opened by another store.
+        let salt = fs::read(&path).unwrap()[..SALT_SIZE].to_vec();
+        store.save(&state).unwrap();
+        assert_eq!(fs::read(&path).unwrap()[..SALT_SIZE].to_vec(), salt);
+        assert_eq!(
+            self::store(&path, "passphrase")
+                .load()
+                .unwrap()
+                .unwrap()
+                .keys
+                .len(),
+            1
+        );
+
+        // Wrong passphrase.
+        assert!(self::store(&path, "other").load().is_err());
+
+        // Tampered file.
+        let mut data = fs::read(&path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        fs::write(&path, data).unwrap();
+        assert!(store.load().is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,34 +1,57 @@
 //! Oasis blockchain simulator.
 use std::{
-    collections::{BTreeMap, HashMap},
-    sync::{Arc, RwLock},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    confidential::ConfidentialCtx, genesis, parity::NullBackend, storage::MemoryMKVS, util,
+    clock::{Clock, ClockSnapshot},
+    confidential::{
+        split_legacy_slot, ConfidentialCtx, DecryptedStorage, Epochs, StorageInspector,
+        StorageMigration,
+    },
+    genesis,
+    parity::NullBackend,
+    queue::{QueueLimits, TransactionQueue},
+    storage::{
+        self, Column, DatabaseMKVS, Journal, KeyValueStore, OverlayMKVS, WriteBatch, WriteSet,
+    },
+    util,
 };
-use ekiden_keymanager::client::MockClient;
+use ekiden_keymanager::{client::KeyManagerClient, ContractId};
 use ethcore::{
-    error::CallError,
+    error::{CallError, ExecutionError},
     executive::{contract_address, Executed, Executive, TransactOptions},
     filter::Filter,
+    header::Header,
     log_entry::{LocalizedLogEntry, LogEntry},
-    receipt::{LocalizedReceipt, TransactionOutcome},
-    state::State,
+    receipt::{LocalizedReceipt, Receipt, TransactionOutcome},
+    state::{CleanupMode, State},
+    trace::{Filter as TraceFilter, FlatTrace, LocalizedTrace},
     transaction::{Action, LocalizedTransaction, SignedTransaction, UnverifiedTransaction},
     types::ids::BlockId,
-    vm::{EnvInfo, Error as VmError},
+    vm::{ConfidentialCtx as EthConfidentialCtx, EnvInfo, Error as VmError, LastHashes},
 };
-use ethereum_types::{Bloom, H256, H64, U256};
+use ethereum_types::{Address, Bloom, H256, H64, U256};
 use failure::{format_err, Error, Fallible};
-use futures::{future, prelude::*, stream};
-use hash::{keccak, KECCAK_EMPTY_LIST_RLP};
+use futures::{
+    future::{self, Loop},
+    prelude::*,
+    stream,
+    sync::oneshot,
+};
+use hash::{keccak, KECCAK_EMPTY_LIST_RLP, KECCAK_NULL_RLP};
 use lazy_static::lazy_static;
 use parity_rpc::v1::types::{
-    Block as EthRpcBlock, BlockTransactions as EthRpcBlockTransactions, Header as EthRpcHeader,
-    RichBlock as EthRpcRichBlock, RichHeader as EthRpcRichHeader, Transaction as EthRpcTransaction,
+    Block as EthRpcBlock, BlockNumber, BlockTransactions as EthRpcBlockTransactions,
+    Bytes as RpcBytes, Header as EthRpcHeader, RichBlock as EthRpcRichBlock,
+    RichHeader as EthRpcRichHeader, Transaction as EthRpcTransaction, H160 as RpcH160,
+    H256 as RpcH256, U256 as RpcU256,
 };
+use rlp::{DecoderError, Rlp, RlpStream};
 use tokio_threadpool::{Builder as ThreadPoolBuilder, ThreadPool};
+use triehash::{ordered_trie_root, trie_root};
 
 /// Boxed future type.
 type BoxFuture<T> = Box<dyn futures::Future<Item = T, Error = failure::Error> + Send>;
@@ -38,48 +61,214 @@ pub const BLOCK_GAS_LIMIT: usize = 16_000_000;
 /// Minimum gas price (in gwei).
 pub const MIN_GAS_PRICE_GWEI: usize = 1;
 
+/// Number of gas limits simulated in parallel in each round of gas estimation.
+const ESTIMATE_GAS_PROBES: u64 = 4;
+
+/// Key under which the best block number is stored in the extra column.
+const BEST_BLOCK_KEY: &[u8] = b"best_block_number";
+
+/// Key present in the extra column iff each version of an MKVS key is stored
+/// under its own key in the state column.
+const VERSIONED_STATE_KEY: &[u8] = b"versioned_state";
+
+/// Key present in the extra column iff confidential storage keys are
+/// encrypted deterministically, rather than with the legacy zero-nonce
+/// scheme of chains created by earlier versions.
+const DETERMINISTIC_STORAGE_KEYS_KEY: &[u8] = b"deterministic_storage_keys";
+
 /// Simulated blockchain state.
 pub struct ChainState {
-    mkvs: MemoryMKVS,
+    store: Arc<dyn KeyValueStore>,
+    state_history: Option<u64>,
     block_number: u64,
     blocks: HashMap<H256, EthereumBlock>,
     block_number_to_hash: HashMap<u64, H256>,
     transactions: HashMap<H256, LocalizedTransaction>,
     receipts: HashMap<H256, LocalizedReceipt>,
+    /// Traces of each transaction, keyed by block hash. Only present for
+    /// blocks mined with tracing enabled.
+    traces: HashMap<H256, Vec<Vec<FlatTrace>>>,
 }
 
 impl ChainState {
-    pub fn new() -> Self {
-        // Initialize genesis state.
-        let mkvs = MemoryMKVS::new();
-        genesis::SPEC
-            .ensure_db_good(Box::new(mkvs.clone()), NullBackend, &Default::default())
-            .expect("genesis initialization must succeed");
-
-        // Initialize chain state.
-        let block_number = 0;
-        let mut blocks = HashMap::new();
-        let mut block_number_to_hash = HashMap::new();
-        let genesis_block = EthereumBlock::new(
-            block_number,
-            H256::zero(),
-            0,
-            U256::from(0),
-            BLOCK_GAS_LIMIT.into(),
-            Default::default(),
-        );
-        let block_hash = genesis_block.hash();
-        blocks.insert(block_hash, genesis_block);
-        block_number_to_hash.insert(block_number, block_hash);
-
-        Self {
-            mkvs,
-            block_number,
-            blocks,
-            block_number_to_hash,
+    /// Open the chain state in the given store.
+    ///
+    /// If the store already contains a chain, it is migrated to the current
+    /// storage layout (using the key manager to re-encrypt confidential
+    /// storage) and its blocks, transactions and receipts are loaded.
+    /// Otherwise the genesis state is initialized.
+    ///
+    /// If `state_history` is set, only the states of that many most recent
+    /// blocks are retained.
+    pub fn new(
+        store: Arc<dyn KeyValueStore>,
+        state_history: Option<u64>,
+        key_manager: &dyn KeyManagerClient,
+    ) -> Fallible<Self> {
+        let mut chain_state = Self {
+            store,
+            state_history,
+            block_number: 0,
+            blocks: HashMap::new(),
+            block_number_to_hash: HashMap::new(),
             transactions: HashMap::new(),
             receipts: HashMap::new(),
+            traces: HashMap::new(),
+        };
+
+        match chain_state.store.get(Column::Extra, BEST_BLOCK_KEY)? {
+            Some(best) => {
+                let best: u64 = rlp::decode(&best).map_err(|err| format_err!("{}", err))?;
+                if chain_state
+                    .store
+                    .get(Column::Extra, VERSIONED_STATE_KEY)?
+                    .is_none()
+                {
+                    chain_state.migrate_state_versions()?;
+                }
+                if chain_state
+                    .store
+                    .get(Column::Extra, DETERMINISTIC_STORAGE_KEYS_KEY)?
+                    .is_none()
+                {
+                    chain_state.migrate_storage_keys(key_manager, best)?;
+                }
+                for number in 0..=best {
+                    chain_state.load_block(number)?;
+                }
+                chain_state.block_number = best;
+
+                info!("Loaded existing chain with best block number {}", best);
+            }
+            None => {
+                // Initialize genesis state.
+                let journal = Arc::new(Mutex::new(Journal::new()));
+                genesis::SPEC
+                    .ensure_db_good(
+                        Box::new(chain_state.mkvs_at(0).with_journal(journal.clone())),
+                        NullBackend,
+                        &Default::default(),
+                    )
+                    .expect("genesis initialization must succeed");
+                let journal = journal.lock().unwrap();
+                let state_root = state_commitment(&H256::zero(), &journal.writes);
+
+                let genesis_block = EthereumBlock::new(
+                    0,
+                    H256::zero(),
+                    0,
+                    U256::from(0),
+                    BLOCK_GAS_LIMIT.into(),
+                    Default::default(),
+                    state_root,
+                    KECCAK_NULL_RLP,
+                    KECCAK_NULL_RLP,
+                );
+                chain_state.insert_block(genesis_block, vec![], None, &journal)?;
+            }
+        }
+
+        Ok(chain_state)
+    }
+
+    /// Store each version of an MKVS key under its own key, for chains
+    /// created when all versions were stored under the key itself.
+    fn migrate_state_versions(&self) -> Fallible<()> {
+        info!("Migrating state to one key per version");
+        let mut batch = WriteBatch::new();
+        let keys = storage::migrate_state_versions(&*self.store, &mut batch)?;
+        batch.put(Column::Extra, VERSIONED_STATE_KEY, &[]);
+        self.store.write(batch)?;
+
+        self.store.flush()?;
+
+        info!("Migrated {} state keys", keys);
+        Ok(())
+    }
+
+    /// Re-encrypt the storage keys of confidential contracts deterministically,
+    /// for chains created when they were encrypted with the legacy scheme.
+    ///
+    /// Every version of each storage slot moves to the slot's new key, and
+    /// the state journal of every block up to `best` is updated to match, all
+    /// in a single batch.
+    fn migrate_storage_keys(&self, key_manager: &dyn KeyManagerClient, best: u64) -> Fallible<()> {
+        info!("Migrating confidential storage keys to deterministic encryption");
+        let mut batch = WriteBatch::new();
+        let mut migrations = HashMap::new();
+        let mut renamed = HashMap::new();
+        let mut versions = vec![];
+        for (key, version, value) in storage::state_versions(&*self.store)? {
+            let (address, legacy_key) = match split_legacy_slot(&key) {
+                Some(slot) => slot,
+                None => continue,
+            };
+            if !migrations.contains_key(&address) {
+                let contract_id = ContractId::from(&keccak(address.to_vec())[..]);
+                let contract_key = key_manager.get_or_create_keys(contract_id)?;
+                migrations.insert(address, StorageMigration::new(&contract_key));
+            }
+            let migration = &migrations[&address];
+
+            // Storage of other contracts, or storage already written with the
+            // current scheme, fails to decrypt as a legacy key.
+            let slot_key = match migration.migrate_key(legacy_key) {
+                Some(slot_key) => slot_key,
+                None => continue,
+            };
+            let value = match value {
+                Some(ref value) => Some(
+                    migration
+                        .migrate_value(legacy_key, &slot_key, value)
+                        .map_err(|err| {
+                            format_err!("failed to migrate storage of {:?}: {}", address, err)
+                        })?,
+                ),
+                None => None,
+            };
+            let mut new_key = address.to_vec();
+            new_key.extend_from_slice(&slot_key);
+
+            storage::delete_state_version(&mut batch, &key, version);
+            versions.push((new_key.clone(), version, value));
+            renamed.insert(key, new_key);
+        }
+        // Only write the new keys after deleting all old ones.
+        for (key, version, value) in &versions {
+            storage::put_state_version(&mut batch, key, *version, value);
+        }
+
+        for number in 0..=best {
+            let journal_key = number.to_be_bytes();
+            let journal = self
+                .store
+                .get(Column::StateJournal, &journal_key)?
+                .ok_or_else(|| format_err!("block {} missing from state journal", number))?;
+            let mut state_keys = vec![];
+            for state_key in Rlp::new(&journal).iter() {
+                state_keys.push(
+                    state_key
+                        .as_val::<Vec<u8>>()
+                        .map_err(|err| format_err!("{}", err))?,
+                );
+            }
+            if !state_keys.iter().any(|key| renamed.contains_key(key)) {
+                continue;
+            }
+
+            let mut stream = RlpStream::new_list(state_keys.len());
+            for state_key in &state_keys {
+                stream.append(renamed.get(state_key).unwrap_or(state_key));
+            }
+            batch.put(Column::StateJournal, &journal_key, &stream.out());
         }
+        batch.put(Column::Extra, DETERMINISTIC_STORAGE_KEYS_KEY, &[]);
+        self.store.write(batch)?;
+
+        self.store.flush()?;
+
+        info!("Migrated {} confidential storage slots", renamed.len());
+        Ok(())
     }
 
     pub fn get_block_by_number(&self, number: u64) -> Option<EthereumBlock> {
@@ -88,40 +277,441 @@ impl ChainState {
             .and_then(|hash| self.blocks.get(hash))
             .cloned()
     }
+
+    /// MKVS view of the state as of the given block number.
+    fn mkvs_at(&self, number: u64) -> DatabaseMKVS {
+        DatabaseMKVS::new(self.store.clone(), number)
+    }
+
+    /// Resolve the number of the block whose state should be used for a
+    /// block identifier.
+    ///
+    /// Returns an error if the block does not exist yet or if its state has
+    /// been pruned.
+    fn state_block_number(&self, id: BlockId) -> Fallible<u64> {
+        let number = match id {
+            BlockId::Hash(hash) => self
+                .blocks
+                .get(&hash)
+                .map(|blk| blk.number)
+                .ok_or_else(|| format_err!("block {:?} not found", hash))?,
+            BlockId::Number(number) => number,
+            BlockId::Latest => self.block_number,
+            BlockId::Earliest => 0,
+        };
+
+        if number > self.block_number {
+            return Err(format_err!(
+                "block {} is in the future (best block is {})",
+                number,
+                self.block_number
+            ));
+        }
+        if let Some(history) = self.state_history {
+            let oldest = self.block_number.saturating_sub(history);
+            if number < oldest {
+                return Err(format_err!(
+                    "state at block {} has been pruned (oldest available is {})",
+                    number,
+                    oldest
+                ));
+            }
+        }
+
+        Ok(number)
+    }
+
+    /// Hashes of the (up to) 256 blocks preceding the given block number,
+    /// most recent first, as used by the `BLOCKHASH` opcode.
+    fn last_hashes(&self, number: u64) -> Arc<LastHashes> {
+        Arc::new(
+            (number.saturating_sub(256)..number)
+                .rev()
+                .map(|number| {
+                    *self
+                        .block_number_to_hash
+                        .get(&number)
+                        .expect("ancestor blocks must exist")
+                })
+                .collect(),
+        )
+    }
+
+    /// Resolve the state and environment in which to simulate a transaction
+    /// at the given block.
+    ///
+    /// Simulations against the latest block run on top of it, as if the
+    /// transaction was included in the next block with the given timestamp.
+    /// Simulations against any other block run on its state, in the
+    /// environment that block had.
+    fn simulation_env(&self, id: BlockId, timestamp: u64) -> Fallible<(u64, EnvInfo)> {
+        let number = self.state_block_number(id)?;
+        let block = self
+            .get_block_by_number(number)
+            .ok_or_else(|| format_err!("block {} not found", number))?;
+
+        let (env_number, timestamp) = match id {
+            BlockId::Latest => (number + 1, timestamp),
+            _ => (number, block.timestamp),
+        };
+        let env_info = EnvInfo {
+            number: env_number,
+            author: Default::default(),
+            timestamp,
+            difficulty: Default::default(),
+            last_hashes: self.last_hashes(env_number),
+            gas_used: Default::default(),
+            gas_limit: U256::max_value(),
+        };
+
+        Ok((number, env_info))
+    }
+
+    /// State of the pending block, i.e., the best block with the given
+    /// transactions applied on top of it, and the environment of the
+    /// pending block (with the given timestamp) after applying them.
+    ///
+    /// Transactions which cannot be applied are skipped. Changes are kept in
+    /// memory and never written to the store.
+    fn pending_state(
+        &self,
+        txns: &[SignedTransaction],
+        key_manager: &KeyManager,
+        gas_limit: U256,
+        timestamp: u64,
+    ) -> (State<NullBackend>, EnvInfo) {
+        let best_block = self
+            .get_block_by_number(self.block_number)
+            .expect("must have a best block");
+        let mut state = State::from_existing(
+            Box::new(OverlayMKVS::new(self.mkvs_at(self.block_number))),
+            NullBackend,
+            U256::zero(),       /* account_start_nonce */
+            Default::default(), /* factories */
+            Some(Box::new(
+                key_manager.confidential_ctx(best_block.hash, self.block_number + 1),
+            )),
+        )
+        .expect("state initialization must succeed");
+
+        let mut env_info = EnvInfo {
+            number: self.block_number + 1,
+            author: Default::default(),
+            timestamp,
+            difficulty: Default::default(),
+            gas_limit,
+            last_hashes: self.last_hashes(self.block_number + 1),
+            gas_used: Default::default(),
+        };
+
+        for txn in txns {
+            if let Ok(outcome) =
+                state.apply(&env_info, genesis::SPEC.engine.machine(), txn, false, true)
+            {
+                env_info.gas_used = outcome.receipt.gas_used;
+            }
+        }
+        state.commit().expect("state commit must succeed");
+
+        (state, env_info)
+    }
+
+    /// Store a new best block together with its receipts, its transaction
+    /// traces (if recorded) and the MKVS writes it performed.
+    ///
+    /// Fails without storing anything if the state could not be read while
+    /// executing the block.
+    fn insert_block(
+        &mut self,
+        block: EthereumBlock,
+        receipts: Vec<LocalizedReceipt>,
+        traces: Option<Vec<Vec<FlatTrace>>>,
+        journal: &Journal,
+    ) -> Fallible<()> {
+        journal.check()?;
+        let writes = &journal.writes;
+        let number = block.number;
+        let hash = block.hash;
+
+        let mut state_keys = RlpStream::new_list(writes.len());
+        for key in writes.keys() {
+            state_keys.append(key);
+        }
+
+        // Persist the block and its state before updating the in-memory
+        // indices.
+        let mut batch = WriteBatch::new();
+        storage::write_state(&*self.store, &mut batch, writes, number, self.state_history)?;
+        batch.put(Column::Blocks, &hash, &encode_block(&block, &receipts));
+        batch.put(Column::BlockIndex, &number.to_be_bytes(), &hash);
+        batch.put(
+            Column::StateJournal,
+            &number.to_be_bytes(),
+            &state_keys.out(),
+        );
+        if let Some(ref traces) = traces {
+            batch.put(Column::Traces, &hash, &encode_traces(traces));
+        }
+        batch.put(Column::Extra, BEST_BLOCK_KEY, &rlp::encode(&number));
+        if number == 0 {
+            // A new chain starts out with the current storage layout.
+            batch.put(Column::Extra, VERSIONED_STATE_KEY, &[]);
+            batch.put(Column::Extra, DETERMINISTIC_STORAGE_KEYS_KEY, &[]);
+        }
+        self.store.write(batch)?;
+
+        self.store.flush()?;
+
+        if let Some(traces) = traces {
+            self.traces.insert(hash, traces);
+        }
+        self.index_block(block, receipts);
+        self.block_number = number;
+
+        Ok(())
+    }
+
+    /// Load a stored block into the in-memory indices.
+    fn load_block(&mut self, number: u64) -> Fallible<()> {
+        let hash = self
+            .store
+            .get(Column::BlockIndex, &number.to_be_bytes())?
+            .ok_or_else(|| format_err!("block {} missing from block index", number))?;
+        let data = self
+            .store
+            .get(Column::Blocks, &hash)?
+            .ok_or_else(|| format_err!("block {} missing from database", number))?;
+        let (block, receipts) = decode_block(&data).map_err(|err| format_err!("{}", err))?;
+
+        if let Some(traces) = self.store.get(Column::Traces, &hash)? {
+            let traces = decode_traces(&traces).map_err(|err| format_err!("{}", err))?;
+            self.traces.insert(block.hash, traces);
+        }
+        self.index_block(block, receipts);
+
+        Ok(())
+    }
+
+    /// Revert the chain to the given block, discarding all later blocks
+    /// together with their transactions, receipts and state updates.
+    fn revert_to(&mut self, number: u64) -> Fallible<()> {
+        // Make sure the state at the target block is still available.
+        self.state_block_number(BlockId::Number(number))?;
+
+        let mut batch = WriteBatch::new();
+        let mut state_keys = BTreeSet::new();
+        for reverted in number + 1..=self.block_number {
+            let key = reverted.to_be_bytes();
+            let journal = self
+                .store
+                .get(Column::StateJournal, &key)?
+                .ok_or_else(|| format_err!("block {} missing from state journal", reverted))?;
+            for state_key in Rlp::new(&journal).iter() {
+                state_keys.insert(
+                    state_key
+                        .as_val::<Vec<u8>>()
+                        .map_err(|err| format_err!("{}", err))?,
+                );
+            }
+
+            batch.delete(Column::Blocks, &self.block_number_to_hash[&reverted]);
+            batch.delete(Column::Traces, &self.block_number_to_hash[&reverted]);
+            batch.delete(Column::BlockIndex, &key);
+            batch.delete(Column::StateJournal, &key);
+        }
+        for state_key in state_keys {
+            storage::revert_state_key(&*self.store, &mut batch, &state_key, number)?;
+        }
+        batch.put(Column::Extra, BEST_BLOCK_KEY, &rlp::encode(&number));
+        self.store.write(batch)?;
+
+        self.store.flush()?;
+
+        for reverted in number + 1..=self.block_number {
+            let hash = self
+                .block_number_to_hash
+                .remove(&reverted)
+                .expect("reverted block must exist");
+            let block = self
+                .blocks
+                .remove(&hash)
+                .expect("reverted block must exist");
+            self.traces.remove(&hash);
+            for txn in &block.transactions {
+                let txn_hash = txn.signed.hash();
+                self.transactions.remove(&txn_hash);
+                self.receipts.remove(&txn_hash);
+            }
+        }
+        self.block_number = number;
+
+        Ok(())
+    }
+
+    /// Localized traces of the transactions in a block which match the
+    /// predicate.
+    ///
+    /// Returns `None` if the block was not mined with tracing enabled.
+    fn localized_traces<F>(
+        &self,
+        block: &EthereumBlock,
+        predicate: F,
+    ) -> Option<Vec<LocalizedTrace>>
+    where
+        F: Fn(&LocalizedTransaction, &FlatTrace) -> bool,
+    {
+        let traces = self.traces.get(&block.hash)?;
+
+        Some(
+            block
+                .transactions
+                .iter()
+                .zip(traces)
+                .flat_map(|(txn, traces)| {
+                    traces
+                        .iter()
+                        .filter(|trace| predicate(txn, trace))
+                        .map(|trace| LocalizedTrace {
+                            action: trace.action.clone(),
+                            result: trace.result.clone(),
+                            subtraces: trace.subtraces,
+                            trace_address: trace.trace_address.clone(),
+                            transaction_number: Some(txn.transaction_index),
+                            transaction_hash: Some(txn.signed.hash()),
+                            block_number: block.number,
+                            block_hash: block.hash,
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+        )
+    }
+
+    fn index_block(&mut self, block: EthereumBlock, receipts: Vec<LocalizedReceipt>) {
+        for txn in &block.transactions {
+            self.transactions.insert(txn.signed.hash(), txn.clone());
+        }
+        for receipt in receipts {
+            self.receipts.insert(receipt.transaction_hash, receipt);
+        }
+        self.block_number_to_hash.insert(block.number, block.hash);
+        self.blocks.insert(block.hash, block);
+    }
+}
+
+/// How submitted transactions are mined into blocks.
+#[derive(Clone, Copy, Debug)]
+pub enum MiningMode {
+    /// Mine a block for each transaction as soon as it is submitted.
+    Instant,
+    /// Seal pending transactions into a block at the given interval, or as
+    /// soon as the block gas limit is reached.
+    Interval(Duration),
+}
+
+/// Channel on which the execution result of a submitted transaction is
+/// reported once it has been mined.
+type ResultSender = oneshot::Sender<Result<ExecutionResult, Error>>;
+
+/// Future resolving to the execution result reported on a channel.
+fn execution_result(
+    receiver: oneshot::Receiver<Result<ExecutionResult, Error>>,
+) -> BoxFuture<ExecutionResult> {
+    Box::new(
+        receiver
+            .map_err(|_| format_err!("transaction was dropped"))
+            .and_then(future::result),
+    )
+}
+
+/// A transaction waiting to be sealed into a block.
+struct PendingTransaction {
+    txn: SignedTransaction,
+    result: ResultSender,
+}
+
+/// Total gas limit of the pending transactions.
+fn pending_gas(pending: &[PendingTransaction]) -> U256 {
+    pending
+        .iter()
+        .fold(U256::zero(), |gas, pending| gas + pending.txn.gas)
+}
+
+/// Chain state saved by `Blockchain::snapshot`.
+struct Snapshot {
+    /// Best block number.
+    number: u64,
+    /// Clock offset and pinned timestamp.
+    clock: ClockSnapshot,
+    /// Impersonated addresses.
+    impersonated: HashSet<Address>,
 }
 
 /// Simulated blockchain.
 pub struct Blockchain {
     gas_price: U256,
     block_gas_limit: U256,
+    mining_mode: MiningMode,
+    tracing: bool,
     simulator_pool: Arc<ThreadPool>,
-    km_client: Arc<MockClient>,
+    key_manager: KeyManager,
+    clock: Clock,
     chain_state: Arc<RwLock<ChainState>>,
+    pending: Mutex<Vec<PendingTransaction>>,
+    queue: Mutex<TransactionQueue<ResultSender>>,
+    new_pending: Mutex<Vec<H256>>,
+    snapshots: Mutex<Vec<Snapshot>>,
+    reverted_to: Mutex<Option<u64>>,
+    impersonated: Mutex<HashSet<Address>>,
 }
 
 impl Blockchain {
     /// Create new simulated blockchain.
-    pub fn new(gas_price: U256, block_gas_limit: U256, km_client: Arc<MockClient>) -> Self {
-        Self {
+    pub fn new(
+        gas_price: U256,
+        block_gas_limit: U256,
+        mining_mode: MiningMode,
+        queue_limits: QueueLimits,
+        km_client: Arc<dyn KeyManagerClient>,
+        store: Arc<dyn KeyValueStore>,
+        state_history: Option<u64>,
+        tracing: bool,
+        epochs: Option<Epochs>,
+    ) -> Fallible<Self> {
+        let chain_state = ChainState::new(store, state_history, &*km_client)?;
+
+        Ok(Self {
             gas_price,
             block_gas_limit,
+            mining_mode,
+            tracing,
             simulator_pool: Arc::new(
                 ThreadPoolBuilder::new()
                     .name_prefix("simulator-pool-")
                     .build(),
             ),
-            km_client,
-            chain_state: Arc::new(RwLock::new(ChainState::new())),
-        }
+            key_manager: KeyManager {
+                client: km_client,
+                epochs,
+            },
+            clock: Clock::default(),
+            chain_state: Arc::new(RwLock::new(chain_state)),
+            pending: Mutex::new(vec![]),
+            queue: Mutex::new(TransactionQueue::new(queue_limits)),
+            new_pending: Mutex::new(vec![]),
+            snapshots: Mutex::new(vec![]),
+            reverted_to: Mutex::new(None),
+            impersonated: Mutex::new(HashSet::new()),
+        })
     }
 
     /// Ethereum state snapshot at given block.
-    pub fn state(&self, _id: BlockId) -> Fallible<State<NullBackend>> {
+    pub fn state(&self, id: BlockId) -> Fallible<State<NullBackend>> {
         let chain_state = self.chain_state.read().unwrap();
+        let number = chain_state.state_block_number(id)?;
 
-        // TODO: support previous block states
         Ok(State::from_existing(
-            Box::new(chain_state.mkvs.clone()),
+            Box::new(chain_state.mkvs_at(number)),
             NullBackend,
             U256::zero(),       /* account_start_nonce */
             Default::default(), /* factories */
@@ -129,11 +719,52 @@ impl Blockchain {
         )?)
     }
 
+    /// Ethereum state snapshot at given RPC block number.
+    ///
+    /// The pending state includes the effects of pending transactions.
+    pub fn state_at(&self, number: BlockNumber) -> Fallible<State<NullBackend>> {
+        match number {
+            BlockNumber::Pending => {
+                let txns = self.pending_transactions();
+                let chain_state = self.chain_state.read().unwrap();
+                let (state, _) = chain_state.pending_state(
+                    &txns,
+                    &self.key_manager,
+                    self.block_gas_limit,
+                    self.clock.next_block_timestamp(),
+                );
+
+                Ok(state)
+            }
+            number => self.state(util::block_number_to_id(number)),
+        }
+    }
+
+    /// Transaction mining mode.
+    pub fn mining_mode(&self) -> MiningMode {
+        self.mining_mode
+    }
+
+    /// Epochs in which ephemeral input keys rotate, if any.
+    pub fn epochs(&self) -> Option<Epochs> {
+        self.key_manager.epochs
+    }
+
+    /// Whether transaction traces are recorded when mining blocks.
+    pub fn tracing(&self) -> bool {
+        self.tracing
+    }
+
     /// Gas price.
     pub fn gas_price(&self) -> U256 {
         self.gas_price
     }
 
+    /// Block gas limit.
+    pub fn block_gas_limit(&self) -> U256 {
+        self.block_gas_limit
+    }
+
     /// Retrieve an Ethereum block given a block identifier.
     pub fn get_block(
         &self,
@@ -277,10 +908,14 @@ impl Blockchain {
     }
 
     /// Submit a raw Ethereum transaction to the chain.
+    ///
+    /// Resolves to the transaction hash once the transaction has been
+    /// accepted, together with a future resolving to its execution result
+    /// once it has been mined.
     pub fn send_raw_transaction(
         &self,
         raw: Vec<u8>,
-    ) -> impl Future<Item = (H256, ExecutionResult), Error = Error> {
+    ) -> impl Future<Item = (H256, BoxFuture<ExecutionResult>), Error = Error> {
         // Decode transaction.
         let decoded: UnverifiedTransaction = match rlp::decode(&raw) {
             Ok(t) => t,
@@ -303,221 +938,916 @@ impl Blockchain {
             return Err(format_err!("Insufficient gas price")).into_future();
         }
 
-        // Mine a block with the transaction.
-        future::done(self.mine_block(txn))
+        future::done(self.submit_transaction(txn))
+    }
+
+    /// Submit a verified transaction for mining, according to the mining mode.
+    ///
+    /// Transactions with a nonce ahead of their sender's next nonce are held
+    /// in the queue until the gap is filled.
+    fn submit_transaction(
+        &self,
+        txn: SignedTransaction,
+    ) -> Result<(H256, BoxFuture<ExecutionResult>), Error> {
+        let txn_hash = txn.hash();
+        let sender = txn.sender();
+
+        {
+            let mut queue = self.queue.lock().unwrap();
+            self.cull_queue(&mut queue);
+
+            if txn.nonce > self.next_nonce(&sender)? {
+                let (result, receiver) = oneshot::channel();
+                if let Some(replaced) = queue.insert(txn, result)? {
+                    let _ = replaced.send(Err(format_err!("transaction was replaced")));
+                }
+                self.new_pending.lock().unwrap().push(txn_hash);
+
+                return Ok((txn_hash, execution_result(receiver)));
+            }
+        }
+
+        // The queue is not locked while mining, so that other submissions
+        // and queries are not blocked by a slow block. Transactions queued
+        // meanwhile are released below, once the nonce has been used.
+        let result: BoxFuture<ExecutionResult> = match self.mining_mode {
+            MiningMode::Instant => {
+                // Mine a block with the transaction.
+                let result = self.mine_block(vec![txn], false)?.remove(0)?;
+
+                Box::new(future::ok(result))
+            }
+            MiningMode::Interval(_) => {
+                let (result, receiver) = oneshot::channel();
+                self.pool_transaction(txn, result)?;
+
+                execution_result(receiver)
+            }
+        };
+        self.new_pending.lock().unwrap().push(txn_hash);
+
+        // Release queued transactions which are now next in line.
+        self.release_queued(&sender)?;
+
+        Ok((txn_hash, result))
+    }
+
+    /// Add a transaction to the pending pool.
+    fn pool_transaction(&self, txn: SignedTransaction, result: ResultSender) -> Fallible<()> {
+        let mut pending = self.pending.lock().unwrap();
+
+        // Seal the pending transactions first if this one does not fit
+        // in the same block.
+        if pending_gas(&pending) + txn.gas > self.block_gas_limit {
+            self.seal_pending(&mut pending, false)?;
+        }
+
+        pending.push(PendingTransaction { txn, result });
+
+        // Seal right away if the block is full.
+        if pending_gas(&pending) >= self.block_gas_limit {
+            self.seal_pending(&mut pending, false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Mine or pool the sender's queued transactions whose nonces are next
+    /// in line.
+    fn release_queued(&self, sender: &Address) -> Fallible<()> {
+        loop {
+            // Take the next transaction, releasing the queue lock before
+            // mining it.
+            let next = {
+                let mut queue = self.queue.lock().unwrap();
+                queue.take(sender, &self.next_nonce(sender)?)
+            };
+            let (txn, result) = match next {
+                Some(next) => next,
+                None => return Ok(()),
+            };
+
+            match self.mining_mode {
+                MiningMode::Instant => {
+                    let _ = result.send(self.mine_block(vec![txn], false)?.remove(0));
+                }
+                MiningMode::Interval(_) => self.pool_transaction(txn, result)?,
+            }
+        }
+    }
+
+    /// Drop queued transactions which have expired.
+    fn cull_queue(&self, queue: &mut TransactionQueue<ResultSender>) {
+        for (txn, result) in queue.cull(Instant::now()) {
+            warn!("Dropping expired queued transaction {:?}", txn.hash());
+            let _ = result.send(Err(format_err!("transaction expired in the queue")));
+        }
+    }
+
+    /// Next nonce expected from the sender, including pending transactions.
+    pub fn next_nonce(&self, sender: &Address) -> Fallible<U256> {
+        // Hold the pending lock so that pending transactions cannot be
+        // mined while the state nonce is read.
+        let pending = self.pending.lock().unwrap();
+        let nonce = self.state(BlockId::Latest)?.nonce(sender)?;
+        let pending_count = pending
+            .iter()
+            .filter(|pending| pending.txn.sender() == *sender)
+            .count();
+
+        Ok(nonce + U256::from(pending_count))
+    }
+
+    /// Transactions in the pending pool, in submission order.
+    pub fn pending_transactions(&self) -> Vec<SignedTransaction> {
+        let pending = self.pending.lock().unwrap();
+        pending.iter().map(|pending| pending.txn.clone()).collect()
+    }
+
+    /// Hashes of all transactions which have been submitted but not yet mined.
+    pub fn pending_transaction_hashes(&self) -> Vec<H256> {
+        let queue = self.queue.lock().unwrap();
+        let pending = self.pending.lock().unwrap();
+
+        pending
+            .iter()
+            .map(|pending| pending.txn.hash())
+            .chain(queue.hashes())
+            .collect()
+    }
+
+    /// Hashes of transactions submitted since the last call.
+    pub fn take_new_pending_transactions(&self) -> Vec<H256> {
+        let mut new_pending = self.new_pending.lock().unwrap();
+        new_pending.drain(..).collect()
+    }
+
+    /// Retrieve a transaction which has been submitted but not yet mined,
+    /// identified by its transaction hash.
+    pub fn get_pending_txn_by_hash(&self, hash: H256) -> Option<SignedTransaction> {
+        let queue = self.queue.lock().unwrap();
+        let pending = self.pending.lock().unwrap();
+
+        queue.get(&hash).cloned().or_else(|| {
+            pending
+                .iter()
+                .map(|pending| &pending.txn)
+                .find(|txn| txn.hash() == hash)
+                .cloned()
+        })
+    }
+
+    /// Mine a block containing all pending transactions, if any.
+    pub fn mine_pending(&self) -> Fallible<()> {
+        let mut pending = self.pending.lock().unwrap();
+        self.seal_pending(&mut pending, false)
+    }
+
+    /// Mine a block containing all pending transactions, or an empty block
+    /// if there are none.
+    ///
+    /// If a timestamp is given, it is used for the block.
+    pub fn mine(&self, timestamp: Option<u64>) -> Fallible<()> {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(timestamp) = timestamp {
+            self.set_next_block_timestamp(timestamp)?;
+        }
+        self.seal_pending(&mut pending, true)
+    }
+
+    /// Move the chain time forward by the given number of seconds.
+    ///
+    /// Returns the total time adjustment, in seconds.
+    pub fn increase_time(&self, seconds: u64) -> i64 {
+        let offset = self.clock.increase_time(seconds);
+        info!("Increased chain time by {} seconds", seconds);
+
+        offset
+    }
+
+    /// Set the timestamp of the next block.
+    ///
+    /// The timestamp must not be lower than that of the latest block.
+    pub fn set_next_block_timestamp(&self, timestamp: u64) -> Fallible<()> {
+        let chain_state = self.chain_state.read().unwrap();
+        let best_block = chain_state
+            .get_block_by_number(chain_state.block_number)
+            .expect("must have a best block");
+        if timestamp < best_block.timestamp {
+            return Err(format_err!(
+                "timestamp {} is lower than the latest block timestamp {}",
+                timestamp,
+                best_block.timestamp
+            ));
+        }
+        self.clock.set_next_block_timestamp(timestamp);
+
+        Ok(())
+    }
+
+    /// Mine the given pending transactions and notify their submitters.
+    ///
+    /// If `allow_empty` is set, a block is mined even if there are no
+    /// transactions to include in it.
+    ///
+    /// The pending lock must be held for the duration of mining so that
+    /// transactions are mined in submission order.
+    fn seal_pending(
+        &self,
+        pending: &mut Vec<PendingTransaction>,
+        allow_empty: bool,
+    ) -> Fallible<()> {
+        if pending.is_empty() && !allow_empty {
+            return Ok(());
+        }
+
+        let (txns, senders): (Vec<_>, Vec<_>) = pending
+            .drain(..)
+            .map(|pending| (pending.txn, pending.result))
+            .unzip();
+        let results = self.mine_block(txns, allow_empty)?;
+
+        for (sender, result) in senders.into_iter().zip(results) {
+            // The submitter may no longer be waiting for the result.
+            let _ = sender.send(result);
+        }
+
+        Ok(())
+    }
+
+    /// Mine a block containing the transactions.
+    ///
+    /// Returns the execution result of each transaction, in order. Transactions
+    /// which cannot be applied are left out of the block, and no block is mined
+    /// if none of them can be applied, unless `allow_empty` is set.
+    fn mine_block(
+        &self,
+        txns: Vec<SignedTransaction>,
+        allow_empty: bool,
+    ) -> Result<Vec<Result<ExecutionResult, Error>>, Error> {
+        self.mine_block_with(txns, allow_empty, |_| Ok(()))
     }
 
-    /// Mine a block containing the transaction.
-    fn mine_block(&self, txn: SignedTransaction) -> Result<(H256, ExecutionResult), Error> {
+    /// Mine a block containing the transactions, after applying a direct
+    /// update to the state.
+    ///
+    /// No block is mined if the update fails.
+    fn mine_block_with<F>(
+        &self,
+        txns: Vec<SignedTransaction>,
+        allow_empty: bool,
+        update: F,
+    ) -> Result<Vec<Result<ExecutionResult, Error>>, Error>
+    where
+        F: FnOnce(&mut State<NullBackend>) -> Fallible<()>,
+    {
         let mut chain_state = self.chain_state.write().unwrap();
 
         // Initialize Ethereum state access functions.
         let best_block = chain_state
             .get_block_by_number(chain_state.block_number)
             .expect("must have a best block");
+        let number = chain_state.block_number + 1;
+        let journal = Arc::new(Mutex::new(Journal::new()));
         let mut state = State::from_existing(
-            Box::new(chain_state.mkvs.clone()),
+            Box::new(chain_state.mkvs_at(number).with_journal(journal.clone())),
             NullBackend,
             U256::zero(),       /* account_start_nonce */
             Default::default(), /* factories */
-            Some(Box::new(ConfidentialCtx::new(
-                best_block.hash,
-                self.km_client.clone(),
-            ))),
+            Some(Box::new(
+                self.key_manager.confidential_ctx(best_block.hash, number),
+            )),
         )
         .expect("state initialization must succeed");
 
         // Initialize Ethereum environment information.
-        let number = chain_state.block_number + 1;
-        let timestamp = util::get_timestamp();
-        let env_info = EnvInfo {
+        let timestamp = self.clock.next_block_timestamp();
+        let mut env_info = EnvInfo {
             number,
             author: Default::default(),
             timestamp,
             difficulty: Default::default(),
             gas_limit: self.block_gas_limit,
-            // TODO: Get 256 last_hashes.
-            last_hashes: Arc::new(vec![best_block.hash]),
+            last_hashes: chain_state.last_hashes(number),
             gas_used: Default::default(),
         };
 
-        // Execute the transaction.
-        let outcome =
-            match state.apply(&env_info, genesis::SPEC.engine.machine(), &txn, false, true) {
+        // Apply the direct state update.
+        update(&mut state)?;
+
+        // Execute the transactions.
+        let mut results = Vec::with_capacity(txns.len());
+        let mut included = vec![];
+        let mut traces = vec![];
+        for txn in txns {
+            let outcome = match state.apply(
+                &env_info,
+                genesis::SPEC.engine.machine(),
+                &txn,
+                self.tracing,
+                true,
+            ) {
                 Ok(outcome) => outcome,
-                Err(err) => return Err(format_err!("{}", err)),
+                Err(err) => {
+                    results.push(Err(format_err!("{}", err)));
+                    continue;
+                }
             };
 
-        // Commit the state updates.
+            // Receipts record the cumulative gas used within the block.
+            let cumulative_gas_used = outcome.receipt.gas_used;
+            let gas_used = cumulative_gas_used - env_info.gas_used;
+            env_info.gas_used = cumulative_gas_used;
+
+            results.push(Ok(ExecutionResult {
+                cumulative_gas_used,
+                gas_used,
+                log_bloom: outcome.receipt.log_bloom,
+                logs: outcome.receipt.logs.clone(),
+                status_code: match outcome.receipt.outcome {
+                    TransactionOutcome::StatusCode(code) => code,
+                    _ => unreachable!("we always use EIP-658 semantics"),
+                },
+                output: outcome.output,
+            }));
+            included.push((txn, gas_used, outcome.receipt));
+            traces.push(outcome.trace);
+        }
+
+        if included.is_empty() && !allow_empty {
+            return Ok(results);
+        }
+        self.clock.take_block_timestamp();
+
+        // Commit the state updates.
         state.commit().expect("state commit must succeed");
 
         // Create a block.
+        let mut log_bloom = Bloom::default();
+        for (_, _, receipt) in &included {
+            log_bloom.accrue_bloom(&receipt.log_bloom);
+        }
+        let journal = journal.lock().unwrap();
+        let state_root = state_commitment(&best_block.state_root, &journal.writes);
+        let transactions_root =
+            ordered_trie_root(included.iter().map(|(txn, _, _)| rlp::encode(txn).to_vec()));
+        let receipts_root = ordered_trie_root(
+            included
+                .iter()
+                .map(|(_, _, receipt)| rlp::encode(receipt).to_vec()),
+        );
         let mut block = EthereumBlock::new(
             number,
             best_block.hash,
             timestamp,
-            outcome.receipt.gas_used,
+            env_info.gas_used,
             self.block_gas_limit,
-            outcome.receipt.log_bloom,
+            log_bloom,
+            state_root,
+            transactions_root,
+            receipts_root,
         );
         let block_hash = block.hash();
-        chain_state.block_number = number;
 
-        // Store the txn.
-        let txn_hash = txn.hash();
-        let localized_txn = LocalizedTransaction {
-            signed: txn.clone().into(),
-            block_number: number,
-            block_hash,
-            transaction_index: 0,
-            cached_sender: None,
-        };
-        block.transactions = vec![localized_txn.clone()];
-        chain_state.transactions.insert(txn_hash, localized_txn);
+        let mut receipts = Vec::with_capacity(included.len());
+        for (index, (txn, gas_used, receipt)) in included.into_iter().enumerate() {
+            let txn_hash = txn.hash();
+
+            // Store the logs.
+            let log_offset = block.logs.len();
+            let logs: Vec<LocalizedLogEntry> = receipt
+                .logs
+                .into_iter()
+                .enumerate()
+                .map(|(i, log)| LocalizedLogEntry {
+                    entry: log,
+                    block_hash: block_hash,
+                    block_number: number,
+                    transaction_hash: txn_hash,
+                    transaction_index: index,
+                    transaction_log_index: i,
+                    log_index: log_offset + i,
+                })
+                .collect();
+            block.logs.extend(logs.clone());
 
-        // Store the logs.
-        let logs: Vec<LocalizedLogEntry> = outcome
-            .receipt
-            .logs
-            .clone()
-            .into_iter()
-            .enumerate()
-            .map(|(i, log)| LocalizedLogEntry {
-                entry: log,
+            // Store the receipt.
+            receipts.push(LocalizedReceipt {
+                transaction_hash: txn_hash,
+                transaction_index: index,
                 block_hash: block_hash,
                 block_number: number,
-                transaction_hash: txn_hash,
-                transaction_index: 0,
-                transaction_log_index: i,
-                log_index: i,
-            })
-            .collect();
-        block.logs = logs.clone();
+                cumulative_gas_used: receipt.gas_used,
+                gas_used,
+                contract_address: match txn.action {
+                    Action::Call(_) => None,
+                    Action::Create => Some(
+                        contract_address(
+                            genesis::SPEC.engine.create_address_scheme(number),
+                            &txn.sender(),
+                            &txn.nonce,
+                            &txn.data,
+                        )
+                        .0,
+                    ),
+                },
+                logs: logs,
+                log_bloom: receipt.log_bloom,
+                outcome: receipt.outcome,
+            });
 
-        // Store the receipt.
-        let localized_receipt = LocalizedReceipt {
-            transaction_hash: txn_hash,
-            transaction_index: 0,
-            block_hash: block_hash,
-            block_number: number,
-            cumulative_gas_used: outcome.receipt.gas_used,
-            gas_used: outcome.receipt.gas_used,
-            contract_address: match txn.action {
-                Action::Call(_) => None,
-                Action::Create => Some(
-                    contract_address(
-                        genesis::SPEC.engine.create_address_scheme(number),
-                        &txn.sender(),
-                        &txn.nonce,
-                        &txn.data,
-                    )
-                    .0,
-                ),
-            },
-            logs: logs,
-            log_bloom: outcome.receipt.log_bloom,
-            outcome: outcome.receipt.outcome.clone(),
-        };
-        chain_state.receipts.insert(txn_hash, localized_receipt);
+            // Store the txn.
+            block.transactions.push(LocalizedTransaction {
+                signed: txn.clone().into(),
+                block_number: number,
+                block_hash,
+                transaction_index: index,
+                cached_sender: Some(txn.sender()),
+            });
+        }
+
+        info!(
+            "Mined block number {:?} containing {} transaction(s). Gas used: {:?}",
+            number,
+            block.transactions.len(),
+            block.gas_used
+        );
 
         // Store the block.
-        chain_state.blocks.insert(block_hash, block.clone());
-        chain_state.block_number_to_hash.insert(number, block_hash);
-
-        // Return the ExecutionResult.
-        let result = ExecutionResult {
-            cumulative_gas_used: outcome.receipt.gas_used,
-            gas_used: outcome.receipt.gas_used,
-            log_bloom: outcome.receipt.log_bloom,
-            logs: outcome.receipt.logs,
-            status_code: match outcome.receipt.outcome {
-                TransactionOutcome::StatusCode(code) => code,
-                _ => unreachable!("we always use EIP-658 semantics"),
-            },
-            output: outcome.output.into(),
+        let traces = match self.tracing {
+            true => Some(traces),
+            false => None,
         };
+        chain_state.insert_block(block, receipts, traces, &journal)?;
 
-        info!(
-            "Mined block number {:?} containing transaction {:?}. Gas used: {:?}",
-            number, txn_hash, result.gas_used
-        );
+        Ok(results)
+    }
 
-        Ok((txn_hash, result))
+    /// Take a snapshot of the chain, its clock and the impersonated
+    /// addresses, returning its identifier.
+    ///
+    /// Snapshot identifiers start at 1 and increase with each snapshot.
+    /// Fails if old states are pruned, as the state of the snapshot could
+    /// be pruned before the chain is reverted to it.
+    pub fn snapshot(&self) -> Fallible<u64> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        let chain_state = self.chain_state.read().unwrap();
+        if chain_state.state_history.is_some() {
+            return Err(format_err!(
+                "snapshots are not supported when the state history is limited"
+            ));
+        }
+
+        snapshots.push(Snapshot {
+            number: chain_state.block_number,
+            clock: self.clock.snapshot(),
+            impersonated: self.impersonated.lock().unwrap().clone(),
+        });
+
+        Ok(snapshots.len() as u64)
+    }
+
+    /// Revert the chain, its clock and the impersonated addresses to the
+    /// state they were in when the given snapshot was taken.
+    ///
+    /// The snapshot and all snapshots taken after it are discarded, as are all
+    /// pending and queued transactions. Returns `false` if there is no such
+    /// snapshot.
+    pub fn revert(&self, id: u64) -> Fallible<bool> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if id == 0 || id > snapshots.len() as u64 {
+            return Ok(false);
+        }
+        let number = snapshots[id as usize - 1].number;
+
+        let mut queue = self.queue.lock().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        let mut chain_state = self.chain_state.write().unwrap();
+
+        chain_state.revert_to(number)?;
+        let snapshot = snapshots.split_off(id as usize - 1).remove(0);
+        self.clock.restore(snapshot.clock);
+        *self.impersonated.lock().unwrap() = snapshot.impersonated;
+
+        let queued = queue.clear();
+        let dropped = pending
+            .drain(..)
+            .map(|pending| pending.result)
+            .chain(queued.into_iter().map(|(_, result)| result));
+        for result in dropped {
+            let _ = result.send(Err(format_err!("chain was reverted")));
+        }
+
+        let mut reverted_to = self.reverted_to.lock().unwrap();
+        *reverted_to = Some(reverted_to.map_or(number, |reverted| reverted.min(number)));
+
+        info!("Reverted chain to block number {}", number);
+
+        Ok(true)
+    }
+
+    /// Lowest block number the chain has been reverted to since the last
+    /// call, if any.
+    pub fn take_reverted_to(&self) -> Option<u64> {
+        self.reverted_to.lock().unwrap().take()
+    }
+
+    /// Accept unsigned transactions from the given address.
+    pub fn impersonate_account(&self, address: Address) {
+        self.impersonated.lock().unwrap().insert(address);
+        info!("Impersonating account {:?}", address);
+    }
+
+    /// Stop accepting unsigned transactions from the given address.
+    ///
+    /// Returns `false` if the address was not impersonated.
+    pub fn stop_impersonating_account(&self, address: &Address) -> bool {
+        self.impersonated.lock().unwrap().remove(address)
+    }
+
+    /// Whether unsigned transactions are accepted from the given address.
+    pub fn is_impersonated(&self, address: &Address) -> bool {
+        self.impersonated.lock().unwrap().contains(address)
+    }
+
+    /// Submit a transaction from an impersonated address.
+    ///
+    /// The transaction is expected to be fake-signed by its sender.
+    pub fn send_impersonated_transaction(
+        &self,
+        txn: SignedTransaction,
+    ) -> impl Future<Item = (H256, BoxFuture<ExecutionResult>), Error = Error> {
+        if !self.is_impersonated(&txn.sender()) {
+            return Err(format_err!(
+                "Account {:?} is not impersonated",
+                txn.sender()
+            ))
+            .into_future();
+        }
+
+        // Check that gas < block gas limit.
+        if txn.gas > self.block_gas_limit {
+            return Err(format_err!("Requested gas greater than block gas limit")).into_future();
+        }
+
+        // Check gas price.
+        if txn.gas_price < self.gas_price {
+            return Err(format_err!("Insufficient gas price")).into_future();
+        }
+
+        future::done(self.submit_transaction(txn))
+    }
+
+    /// Set the balance of an account.
+    pub fn set_balance(&self, address: Address, balance: U256) -> Fallible<()> {
+        self.update_account(address, |state| set_balance(state, &address, balance))
+    }
+
+    /// Set the nonce of an account.
+    pub fn set_nonce(&self, address: Address, nonce: U256) -> Fallible<()> {
+        self.update_account(address, |state| set_nonce(state, &address, nonce))
+    }
+
+    /// Set the code of an account.
+    pub fn set_code(&self, address: Address, code: Vec<u8>) -> Fallible<()> {
+        self.update_account(address, |state| Ok(state.reset_code(&address, code)?))
+    }
+
+    /// Set a storage slot of an account.
+    pub fn set_storage(&self, address: Address, key: H256, value: H256) -> Fallible<()> {
+        self.update_account(
+            address,
+            |state| Ok(state.set_storage(&address, key, value)?),
+        )
+    }
+
+    /// Decrypt a storage slot of a confidential contract at the given block.
+    ///
+    /// The key is encrypted with the contract's state key to look up the
+    /// stored value, which is decrypted with the nonce appended to it.
+    /// Returns `None` if the slot is empty.
+    pub fn decrypted_storage(
+        &self,
+        address: Address,
+        key: H256,
+        id: BlockId,
+    ) -> Fallible<Option<DecryptedStorage>> {
+        let chain_state = self.chain_state.read().unwrap();
+        let number = chain_state.state_block_number(id)?;
+        let block = chain_state
+            .get_block_by_number(number)
+            .expect("state block must exist");
+
+        let (mut inspector, decrypted) =
+            StorageInspector::new(self.key_manager.confidential_ctx(block.hash, number + 1));
+        inspector
+            .activate(Some(address))
+            .map_err(|err| format_err!("{}", err))?;
+        let state = State::from_existing(
+            Box::new(chain_state.mkvs_at(number)),
+            NullBackend,
+            U256::zero(),       /* account_start_nonce */
+            Default::default(), /* factories */
+            Some(Box::new(inspector)),
+        )?;
+        state.storage_at(&address, &key)?;
+
+        let decrypted = decrypted.lock().unwrap().take();
+        Ok(decrypted)
+    }
+
+    /// Update the state of an account directly, recording the change in a new
+    /// block.
+    ///
+    /// Pending transactions are mined first, so that the update applies on top
+    /// of them.
+    fn update_account<F>(&self, address: Address, update: F) -> Fallible<()>
+    where
+        F: FnOnce(&mut State<NullBackend>) -> Fallible<()>,
+    {
+        let mut pending = self.pending.lock().unwrap();
+        self.seal_pending(&mut pending, false)?;
+        self.mine_block_with(vec![], true, update)?;
+
+        info!("Updated state of account {:?}", address);
+
+        Ok(())
+    }
+
+    /// Traces of the transactions in a block.
+    ///
+    /// Returns `None` if the block does not exist or was not mined with
+    /// tracing enabled.
+    pub fn block_traces(&self, number: u64) -> Option<Vec<LocalizedTrace>> {
+        let chain_state = self.chain_state.read().unwrap();
+        let block = chain_state.get_block_by_number(number)?;
+
+        chain_state.localized_traces(&block, |_, _| true)
+    }
+
+    /// Traces of a transaction.
+    ///
+    /// Returns `None` if the transaction does not exist or its block was not
+    /// mined with tracing enabled.
+    pub fn transaction_traces(&self, hash: H256) -> Option<Vec<LocalizedTrace>> {
+        let chain_state = self.chain_state.read().unwrap();
+        let number = chain_state.transactions.get(&hash)?.block_number;
+        let block = chain_state.get_block_by_number(number)?;
+
+        chain_state.localized_traces(&block, |txn, _| txn.signed.hash() == hash)
+    }
+
+    /// Traces in the given range of blocks which match the filter.
+    ///
+    /// Blocks which were not mined with tracing enabled are skipped.
+    pub fn filter_traces(&self, filter: TraceFilter) -> Vec<LocalizedTrace> {
+        let chain_state = self.chain_state.read().unwrap();
+        let to_block = (filter.range.end as u64).min(chain_state.block_number + 1);
+
+        (filter.range.start as u64..to_block)
+            .filter_map(|number| chain_state.get_block_by_number(number))
+            .filter_map(|block| {
+                chain_state.localized_traces(&block, |_, trace| filter.matches(trace))
+            })
+            .flat_map(|traces| traces)
+            .collect()
     }
 
     /// Simulate a transaction against a given block.
     ///
+    /// For the latest block, the transaction is simulated as if it was
+    /// included in the next block. For the pending block, it is simulated
+    /// after the pending transactions. For any other block, it is simulated on
+    /// that block's state with that block's number, timestamp and last hashes.
+    ///
+    /// If given, the state overrides are applied before the simulation. They
+    /// are never written to the chain.
+    ///
     /// The simulated transaction is executed in a dedicated thread pool to
     /// avoid blocking I/O processing.
     ///
-    /// # Notes
-    ///
-    /// Confidential contracts are not supported.
+    /// Calls to confidential contracts are decrypted and their output is
+    /// encrypted for the caller, as when the transaction is mined.
     pub fn simulate_transaction(
         &self,
         transaction: SignedTransaction,
-        _id: BlockId,
+        number: BlockNumber,
+        overrides: Option<StateOverride>,
     ) -> impl Future<Item = Executed, Error = CallError> {
-        let simulator_pool = self.simulator_pool.clone();
-        let chain_state = self.chain_state.clone();
+        self.simulate(transaction, number, overrides, false)
+    }
+
+    /// Simulate a transaction against a given block, with call and VM
+    /// tracing enabled.
+    ///
+    /// See `simulate_transaction`.
+    pub fn trace_call(
+        &self,
+        transaction: SignedTransaction,
+        number: BlockNumber,
+    ) -> impl Future<Item = Executed, Error = CallError> {
+        self.simulate(transaction, number, None, true)
+    }
+
+    fn simulate(
+        &self,
+        transaction: SignedTransaction,
+        number: BlockNumber,
+        overrides: Option<StateOverride>,
+        tracing: bool,
+    ) -> impl Future<Item = Executed, Error = CallError> {
+        let simulation = self.simulation(number);
 
         // Execute simulation in a dedicated thread pool to avoid blocking
         // I/O processing with simulations.
-        simulator_pool.spawn_handle(future::lazy(move || {
-            let chain_state = chain_state.read().unwrap();
+        self.simulator_pool.spawn_handle(future::lazy(move || {
+            simulation.run(&transaction, overrides.as_ref(), tracing)
+        }))
+    }
 
-            let best_block = chain_state
-                .get_block_by_number(chain_state.block_number)
-                .expect("must have a best block");
+    /// Context for simulating transactions against a given block.
+    fn simulation(&self, number: BlockNumber) -> Simulation {
+        let pending = match number {
+            BlockNumber::Pending => Some(self.pending_transactions()),
+            _ => None,
+        };
 
-            let env_info = EnvInfo {
-                number: chain_state.block_number + 1,
-                author: Default::default(),
-                timestamp: util::get_timestamp(),
-                difficulty: Default::default(),
-                // TODO: Get 256 last hashes.
-                last_hashes: Arc::new(vec![best_block.hash]),
-                gas_used: Default::default(),
-                gas_limit: U256::max_value(),
+        Simulation {
+            chain_state: self.chain_state.clone(),
+            key_manager: self.key_manager.clone(),
+            block_gas_limit: self.block_gas_limit,
+            timestamp: self.clock.next_block_timestamp(),
+            number,
+            pending,
+        }
+    }
+
+    /// Re-execute a mined transaction with call and VM tracing enabled.
+    ///
+    /// The transaction is executed on the state of its block's parent, after
+    /// the transactions preceding it in the block. Resolves to `None` if the
+    /// transaction does not exist.
+    pub fn trace_transaction(
+        &self,
+        hash: H256,
+    ) -> impl Future<Item = Option<(SignedTransaction, Executed)>, Error = Error> {
+        let chain_state = self.chain_state.clone();
+        let key_manager = self.key_manager.clone();
+
+        // Execute in the simulator pool to avoid blocking I/O processing.
+        self.simulator_pool.spawn_handle(future::lazy(move || {
+            let chain_state = chain_state.read().unwrap();
+
+            let mut txn = match chain_state.transactions.get(&hash) {
+                Some(txn) => txn.clone(),
+                None => return Ok(None),
             };
-            let machine = genesis::SPEC.engine.machine();
-            let options = TransactOptions::with_no_tracing()
-                .dont_check_nonce()
-                .save_output_from_contract();
+            let block = chain_state
+                .get_block_by_number(txn.block_number)
+                .expect("transaction block must exist");
+            let parent = chain_state.state_block_number(BlockId::Number(block.number - 1))?;
+
             let mut state = State::from_existing(
-                Box::new(chain_state.mkvs.clone()),
+                Box::new(OverlayMKVS::new(chain_state.mkvs_at(parent))),
                 NullBackend,
                 U256::zero(),       /* account_start_nonce */
                 Default::default(), /* factories */
-                None,               /* confidential_ctx */
+                Some(Box::new(
+                    key_manager.confidential_ctx(block.parent_hash, block.number),
+                )),
             )
             .expect("state initialization must succeed");
+            let mut env_info = EnvInfo {
+                number: block.number,
+                author: Default::default(),
+                timestamp: block.timestamp,
+                difficulty: Default::default(),
+                gas_limit: block.gas_limit,
+                last_hashes: chain_state.last_hashes(block.number),
+                gas_used: Default::default(),
+            };
+            let machine = genesis::SPEC.engine.machine();
 
-            Ok(Executive::new(&mut state, &env_info, machine)
-                .transact_virtual(&transaction, options)?)
+            // Replay the preceding transactions in the block.
+            for preceding in &block.transactions[..txn.transaction_index] {
+                let preceding = signed_transaction(&mut preceding.clone());
+                let outcome = state
+                    .apply(&env_info, machine, &preceding, false, true)
+                    .map_err(|err| format_err!("{}", err))?;
+                env_info.gas_used = outcome.receipt.gas_used;
+            }
+
+            let txn = signed_transaction(&mut txn);
+            let executed = Executive::new(&mut state, &env_info, machine)
+                .transact(
+                    &txn,
+                    TransactOptions::with_tracing_and_vm_tracing().save_output_from_contract(),
+                )
+                .map_err(|err| format_err!("{}", err))?;
+
+            Ok(Some((txn, executed)))
         }))
     }
 
     /// Estimates gas against a given block.
     ///
-    /// Uses `simulate_transaction` internally.
-    ///
-    /// # Notes
-    ///
-    /// Confidential contracts are not supported.
+    /// Searches for the lowest gas limit at which the transaction succeeds,
+    /// bounded by the transaction's gas limit and the block gas limit. Each
+    /// round of the search simulates several gas limits in parallel in the
+    /// simulator pool. Fails if the transaction fails (e.g., reverts) even
+    /// with the upper bound; any failure with a lower gas limit only means
+    /// that the limit is too low.
     pub fn estimate_gas(
         &self,
         transaction: SignedTransaction,
-        id: BlockId,
-    ) -> impl Future<Item = U256, Error = CallError> {
-        self.simulate_transaction(transaction, id)
-            .inspect(|executed| match &executed.exception {
-                Some(VmError::Reverted) | Some(VmError::OutOfGas) => {
-                    eprintln!("vm error: {:?}", executed.exception.as_ref().unwrap());
+        number: BlockNumber,
+    ) -> impl Future<Item = U256, Error = EstimateGasError> {
+        let simulation = Arc::new(self.simulation(number));
+        let simulator_pool = self.simulator_pool.clone();
+        let upper_bound = transaction.gas.min(self.block_gas_limit);
+        let capped = upper_bound < self.block_gas_limit;
+        let sender = transaction.sender();
+        let unsigned = transaction.as_unsigned().clone();
+
+        // Simulate the transaction with the given gas limit.
+        let probe = move |gas: U256| -> SimulationFuture {
+            let simulation = simulation.clone();
+            let mut transaction = unsigned.clone();
+            transaction.gas = gas;
+            let transaction = transaction.fake_sign(sender);
+
+            Box::new(simulator_pool.spawn_handle(future::lazy(move || {
+                simulation.run(&transaction, None, false)
+            })))
+        };
+
+        probe(upper_bound)
+            .map_err(move |err| match err {
+                CallError::Execution(ExecutionError::NotEnoughBaseGas { .. }) if capped => {
+                    EstimateGasError::GasAllowanceExceeded(upper_bound)
                 }
-                _ => {}
+                err => EstimateGasError::Call(err),
+            })
+            .and_then(move |executed| {
+                match executed.exception {
+                    Some(VmError::OutOfGas) if capped => {
+                        return future::Either::A(future::err(
+                            EstimateGasError::GasAllowanceExceeded(upper_bound),
+                        ));
+                    }
+                    Some(error) => {
+                        return future::Either::A(future::err(EstimateGasError::Execution {
+                            error,
+                            output: executed.output,
+                        }));
+                    }
+                    None => {}
+                }
+
+                // The transaction cannot succeed with less gas than it used.
+                let lower_bound = (executed.gas_used + executed.refunded).saturating_sub(1.into());
+
+                // Invariant: the transaction fails with `lo` and succeeds with `hi`.
+                future::Either::B(
+                    future::loop_fn((lower_bound, upper_bound), move |(lo, hi)| {
+                        if hi - lo <= 1.into() {
+                            return future::Either::A(future::ok(Loop::Break(hi)));
+                        }
+
+                        let mut candidates: Vec<U256> = (1..=ESTIMATE_GAS_PROBES)
+                            .map(|i| lo + (hi - lo) * i / (ESTIMATE_GAS_PROBES + 1))
+                            .filter(|gas| *gas > lo && *gas < hi)
+                            .collect();
+                        candidates.dedup();
+
+                        // A probe which cannot be simulated counts as failed.
+                        let probes = candidates.into_iter().map(|gas| {
+                            probe(gas).then(move |result| {
+                                let success = result
+                                    .map(|executed| executed.exception.is_none())
+                                    .unwrap_or(false);
+                                Ok::<_, CallError>((gas, success))
+                            })
+                        });
+
+                        future::Either::B(future::join_all(probes).map(move |results| {
+                            let hi = results
+                                .iter()
+                                .filter(|(_, success)| *success)
+                                .map(|(gas, _)| *gas)
+                                .fold(hi, U256::min);
+                            let lo = results
+                                .iter()
+                                .filter(|(gas, success)| !*success && *gas < hi)
+                                .map(|(gas, _)| *gas)
+                                .fold(lo, U256::max);
+
+                            Loop::Continue((lo, hi))
+                        }))
+                    })
+                    .map_err(EstimateGasError::Call),
+                )
             })
-            .map(|executed| executed.gas_used + executed.refunded)
     }
 
     /// Looks up logs based on the given filter.
@@ -588,6 +1918,225 @@ pub struct ExecutionResult {
     pub output: Vec<u8>,
 }
 
+/// Key manager, from which confidential contexts are created.
+#[derive(Clone)]
+struct KeyManager {
+    client: Arc<dyn KeyManagerClient>,
+    /// Epochs in which ephemeral input keys rotate. If not set, transactions
+    /// are encrypted to the long-term keys of contracts.
+    epochs: Option<Epochs>,
+}
+
+impl KeyManager {
+    /// Confidential context for executing transactions in the given block.
+    fn confidential_ctx(&self, prev_block_hash: H256, block_number: u64) -> ConfidentialCtx {
+        let ctx = ConfidentialCtx::new(prev_block_hash, self.client.clone());
+        match self.epochs {
+            Some(epochs) => ctx.with_input_epochs(epochs.accepted(block_number)),
+            None => ctx,
+        }
+    }
+}
+
+/// Boxed future resolving to the result of a simulation.
+type SimulationFuture = Box<dyn futures::Future<Item = Executed, Error = CallError> + Send>;
+
+/// Context for simulating transactions against a given block.
+struct Simulation {
+    chain_state: Arc<RwLock<ChainState>>,
+    key_manager: KeyManager,
+    block_gas_limit: U256,
+    timestamp: u64,
+    number: BlockNumber,
+    /// Pending transactions, if simulating against the pending block.
+    pending: Option<Vec<SignedTransaction>>,
+}
+
+impl Simulation {
+    /// Simulate a transaction.
+    ///
+    /// Blocks while the simulation runs, so this should be called in the
+    /// simulator pool.
+    fn run(
+        &self,
+        transaction: &SignedTransaction,
+        overrides: Option<&StateOverride>,
+        tracing: bool,
+    ) -> Result<Executed, CallError> {
+        let chain_state = self.chain_state.read().unwrap();
+
+        let (mut state, env_info) = match self.pending {
+            Some(ref txns) => {
+                let (state, mut env_info) = chain_state.pending_state(
+                    txns,
+                    &self.key_manager,
+                    self.block_gas_limit,
+                    self.timestamp,
+                );
+                env_info.gas_used = Default::default();
+                env_info.gas_limit = U256::max_value();
+                (state, env_info)
+            }
+            None => {
+                let (number, env_info) = chain_state
+                    .simulation_env(
+                        util::block_number_to_id(self.number.clone()),
+                        self.timestamp,
+                    )
+                    .map_err(|err| {
+                        CallError::Execution(ExecutionError::Internal(err.to_string()))
+                    })?;
+                let block = chain_state
+                    .get_block_by_number(number)
+                    .expect("simulated block must exist");
+                let state = State::from_existing(
+                    Box::new(chain_state.mkvs_at(number)),
+                    NullBackend,
+                    U256::zero(),       /* account_start_nonce */
+                    Default::default(), /* factories */
+                    Some(Box::new(
+                        self.key_manager
+                            .confidential_ctx(block.hash, env_info.number),
+                    )),
+                )
+                .expect("state initialization must succeed");
+                (state, env_info)
+            }
+        };
+        if let Some(overrides) = overrides {
+            apply_state_override(&mut state, overrides)
+                .map_err(|err| CallError::Execution(ExecutionError::Internal(err.to_string())))?;
+        }
+        let machine = genesis::SPEC.engine.machine();
+        let mut executive = Executive::new(&mut state, &env_info, machine);
+
+        Ok(match tracing {
+            true => executive.transact_virtual(
+                transaction,
+                TransactOptions::with_tracing_and_vm_tracing()
+                    .dont_check_nonce()
+                    .save_output_from_contract(),
+            )?,
+            false => executive.transact_virtual(
+                transaction,
+                TransactOptions::with_no_tracing()
+                    .dont_check_nonce()
+                    .save_output_from_contract(),
+            )?,
+        })
+    }
+}
+
+/// Gas estimation error.
+#[derive(Debug)]
+pub enum EstimateGasError {
+    /// The transaction could not be simulated.
+    Call(CallError),
+    /// The simulated transaction failed, with the given output (e.g., revert
+    /// data).
+    Execution { error: VmError, output: Vec<u8> },
+    /// The transaction ran out of gas with the given gas limit of the
+    /// caller, which is below the block gas limit.
+    GasAllowanceExceeded(U256),
+}
+
+/// Overrides of an account's state for a simulated call.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct AccountOverride {
+    pub balance: Option<RpcU256>,
+    pub nonce: Option<RpcU256>,
+    pub code: Option<RpcBytes>,
+    /// Storage replacing the entire storage of the account.
+    pub state: Option<BTreeMap<RpcH256, RpcH256>>,
+    /// Storage slots to override, leaving the other slots unchanged.
+    pub state_diff: Option<BTreeMap<RpcH256, RpcH256>>,
+}
+
+/// Account state overrides for a simulated call, keyed by address.
+pub type StateOverride = BTreeMap<RpcH160, AccountOverride>;
+
+/// Signed transaction of a mined transaction.
+///
+/// Transactions from impersonated accounts are fake-signed by their sender.
+fn signed_transaction(txn: &mut LocalizedTransaction) -> SignedTransaction {
+    let sender = txn.sender();
+    match SignedTransaction::new(txn.signed.clone()) {
+        Ok(signed) if signed.sender() == sender => signed,
+        _ => txn.signed.as_unsigned().clone().fake_sign(sender),
+    }
+}
+
+/// Set the balance of an account.
+fn set_balance(state: &mut State<NullBackend>, address: &Address, balance: U256) -> Fallible<()> {
+    let current = state.balance(address)?;
+    if balance > current {
+        state.add_balance(address, &(balance - current), CleanupMode::NoEmpty)?;
+    } else {
+        state.sub_balance(address, &(current - balance), &mut CleanupMode::NoEmpty)?;
+    }
+
+    Ok(())
+}
+
+/// Set the nonce of an account.
+///
+/// The state cannot set a nonce directly, so the account is rebuilt with the
+/// nonce, keeping its balance and code.
+fn set_nonce(state: &mut State<NullBackend>, address: &Address, nonce: U256) -> Fallible<()> {
+    let balance = state.balance(address)?;
+    let code = state.code(address)?;
+    state.kill_account(address);
+    state.new_contract(address, balance, nonce);
+    if let Some(code) = code {
+        state.reset_code(address, (*code).clone())?;
+    }
+
+    Ok(())
+}
+
+/// Apply state overrides to a state.
+fn apply_state_override(state: &mut State<NullBackend>, overrides: &StateOverride) -> Fallible<()> {
+    for (address, account) in overrides {
+        let address: Address = address.clone().into();
+
+        if account.state.is_some() && account.state_diff.is_some() {
+            return Err(format_err!(
+                "account {:?} has both state and stateDiff overrides",
+                address
+            ));
+        }
+
+        // Recreate the account to clear its storage for a `state` override,
+        // and to set an overridden nonce.
+        if account.state.is_some() || account.nonce.is_some() {
+            let nonce = match account.nonce {
+                Some(ref nonce) => nonce.clone().into(),
+                None => state.nonce(&address)?,
+            };
+            set_nonce(state, &address, nonce)?;
+        }
+        if let Some(ref storage) = account.state {
+            for (key, value) in storage {
+                state.set_storage(&address, key.clone().into(), value.clone().into())?;
+            }
+        }
+        if let Some(ref storage) = account.state_diff {
+            for (key, value) in storage {
+                state.set_storage(&address, key.clone().into(), value.clone().into())?;
+            }
+        }
+        if let Some(ref balance) = account.balance {
+            set_balance(state, &address, balance.clone().into())?;
+        }
+        if let Some(ref code) = account.code {
+            state.reset_code(&address, code.clone().into())?;
+        }
+    }
+
+    Ok(())
+}
+
 /// A wrapper that exposes a simulated Ethereum block.
 #[derive(Clone, Debug)]
 pub struct EthereumBlock {
@@ -598,6 +2147,9 @@ pub struct EthereumBlock {
     gas_used: U256,
     gas_limit: U256,
     log_bloom: Bloom,
+    state_root: H256,
+    transactions_root: H256,
+    receipts_root: H256,
     logs: Vec<LocalizedLogEntry>,
     transactions: Vec<LocalizedTransaction>,
 }
@@ -611,19 +2163,53 @@ impl EthereumBlock {
         gas_used: U256,
         gas_limit: U256,
         log_bloom: Bloom,
+        state_root: H256,
+        transactions_root: H256,
+        receipts_root: H256,
     ) -> Self {
-        // TODO: better blockhash
-        Self {
+        let mut block = Self {
             number,
             parent_hash,
             timestamp,
             logs: vec![],
             transactions: vec![],
-            hash: keccak(number.to_string()).into(),
+            hash: H256::zero(),
             gas_used,
             gas_limit,
             log_bloom,
-        }
+            state_root,
+            transactions_root,
+            receipts_root,
+        };
+        block.hash = block.header().hash();
+
+        block
+    }
+
+    /// Ethereum block header.
+    ///
+    /// The block hash is the hash of the RLP-encoded header, including the
+    /// (dummy) PoW seal fields so that it can be verified by Ethereum tooling.
+    fn header(&self) -> Header {
+        let mut header = Header::new();
+        header.set_parent_hash(self.parent_hash);
+        header.set_timestamp(self.timestamp);
+        header.set_number(self.number);
+        header.set_author(Address::default());
+        header.set_transactions_root(self.transactions_root);
+        header.set_uncles_hash(KECCAK_EMPTY_LIST_RLP);
+        header.set_state_root(self.state_root);
+        header.set_receipts_root(self.receipts_root);
+        header.set_log_bloom(self.log_bloom);
+        header.set_gas_used(self.gas_used);
+        header.set_gas_limit(self.gas_limit);
+        header.set_difficulty(U256::zero());
+        header.set_seal(vec![
+            rlp::encode(&H256::default()).to_vec(),
+            rlp::encode(&H64::default()).to_vec(),
+        ]);
+
+        header
     }
 
     /// Ethereum block number as an u64.
@@ -651,10 +2237,9 @@ impl EthereumBlock {
                 uncles_hash: KECCAK_EMPTY_LIST_RLP.into(), /* empty list */
                 author: Default::default(),
                 miner: Default::default(),
-                // TODO: state root
-                state_root: Default::default(),
-                transactions_root: Default::default(),
-                receipts_root: Default::default(),
+                state_root: self.state_root.into(),
+                transactions_root: self.transactions_root.into(),
+                receipts_root: self.receipts_root.into(),
                 number: Some(self.number.into()),
                 gas_used: self.gas_used.into(),
                 gas_limit: self.gas_limit.into(),
@@ -714,3 +2299,643 @@ impl EthereumBlock {
         }
     }
 }
+
+/// Commitment to the state after a block.
+///
+/// The MKVS is not a Merkle-Patricia trie, so instead of an Ethereum state
+/// root this chains the parent block's commitment with a trie root over the
+/// keys written by the block.
+fn state_commitment(parent: &H256, writes: &WriteSet) -> H256 {
+    let writes_root = trie_root(writes.iter().map(|(key, value)| {
+        // Prefix values so that removals are distinguishable from empty values.
+        let value = match value {
+            Some(value) => [&[1u8][..], &value[..]].concat(),
+            None => vec![0],
+        };
+        (key.clone(), value)
+    }));
+
+    keccak([parent.as_ref(), writes_root.as_ref()].concat())
+}
+
+/// Encode a block together with its receipts for storage.
+///
+/// The encoding is an RLP list of the header fields followed by a list of
+/// `[transaction, sender, gas_used, contract_address, receipt]` entries.
+fn encode_block(block: &EthereumBlock, receipts: &[LocalizedReceipt]) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(11);
+    stream
+        .append(&block.number)
+        .append(&block.timestamp)
+        .append(&block.hash)
+        .append(&block.parent_hash)
+        .append(&block.gas_used)
+        .append(&block.gas_limit)
+        .append(&block.log_bloom)
+        .append(&block.state_root)
+        .append(&block.transactions_root)
+        .append(&block.receipts_root);
+
+    stream.begin_list(block.transactions.len());
+    for (txn, receipt) in block.transactions.iter().zip(receipts) {
+        stream.begin_list(5);
+        stream.append(&txn.signed);
+        stream.append(&txn.clone().sender());
+        stream.append(&receipt.gas_used);
+        match receipt.contract_address {
+            Some(ref address) => stream.append(address),
+            None => stream.append_empty_data(),
+        };
+        stream.append(&Receipt::new(
+            receipt.outcome.clone(),
+            receipt.cumulative_gas_used,
+            receipt.logs.iter().map(|log| log.entry.clone()).collect(),
+        ));
+    }
+
+    stream.out()
+}
+
+/// Decode a block and its receipts encoded by `encode_block`.
+fn decode_block(data: &[u8]) -> Result<(EthereumBlock, Vec<LocalizedReceipt>), DecoderError> {
+    let rlp = Rlp::new(data);
+    let mut block = EthereumBlock {
+        number: rlp.val_at(0)?,
+        timestamp: rlp.val_at(1)?,
+        hash: rlp.val_at(2)?,
+        parent_hash: rlp.val_at(3)?,
+        gas_used: rlp.val_at(4)?,
+        gas_limit: rlp.val_at(5)?,
+        log_bloom: rlp.val_at(6)?,
+        state_root: rlp.val_at(7)?,
+        transactions_root: rlp.val_at(8)?,
+        receipts_root: rlp.val_at(9)?,
+        logs: vec![],
+        transactions: vec![],
+    };
+
+    let mut receipts = vec![];
+    for (index, entry) in rlp.at(10)?.iter().enumerate() {
+        let signed: UnverifiedTransaction = entry.val_at(0)?;
+        let sender: Address = entry.val_at(1)?;
+        let gas_used: U256 = entry.val_at(2)?;
+        let contract_address: Option<Address> = match entry.at(3)?.is_empty() {
+            true => None,
+            false => Some(entry.val_at(3)?),
+        };
+        let receipt: Receipt = entry.val_at(4)?;
+        let txn_hash = signed.hash();
+        let log_offset = block.logs.len();
+
+        let logs: Vec<LocalizedLogEntry> = receipt
+            .logs
+            .into_iter()
+            .enumerate()
+            .map(|(i, log)| LocalizedLogEntry {
+                entry: log,
+                block_hash: block.hash,
+                block_number: block.number,
+                transaction_hash: txn_hash,
+                transaction_index: index,
+                transaction_log_index: i,
+                log_index: log_offset + i,
+            })
+            .collect();
+        block.logs.extend(logs.iter().cloned());
+
+        block.transactions.push(LocalizedTransaction {
+            signed,
+            block_number: block.number,
+            block_hash: block.hash,
+            transaction_index: index,
+            cached_sender: Some(sender),
+        });
+        receipts.push(LocalizedReceipt {
+            transaction_hash: txn_hash,
+            transaction_index: index,
+            block_hash: block.hash,
+            block_number: block.number,
+            cumulative_gas_used: receipt.gas_used,
+            gas_used,
+            contract_address,
+            logs,
+            log_bloom: receipt.log_bloom,
+            outcome: receipt.outcome,
+        });
+    }
+
+    Ok((block, receipts))
+}
+
+/// Encode the transaction traces of a block for storage.
+///
+/// The encoding is an RLP list of the traces of each transaction.
+fn encode_traces(traces: &[Vec<FlatTrace>]) -> Vec<u8> {
+    let mut stream = RlpStream::new_list(traces.len());
+    for txn_traces in traces {
+        stream.append_list(txn_traces);
+    }
+
+    stream.out()
+}
+
+/// Decode transaction traces encoded by `encode_traces`.
+fn decode_traces(data: &[u8]) -> Result<Vec<Vec<FlatTrace>>, DecoderError> {
+    Rlp::new(data)
+        .iter()
+        .map(|txn_traces| txn_traces.as_list())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ekiden_crypto::mrae::{
+        deoxysii::{DeoxysII, KEY_SIZE},
+        nonce::NONCE_SIZE,
+    };
+    use ekiden_keymanager::client::MockClient;
+    use ethcore::{mkvs::MKVS, transaction::Transaction};
+
+    use super::*;
+    use crate::storage::{DiskStore, MemoryStore};
+
+    /// Gas price of the test chains.
+    const GAS_PRICE: u64 = 1_000_000_000;
+
+    /// Open (or create) a chain in the given store.
+    fn open_blockchain(
+        store: Arc<dyn KeyValueStore>,
+        km_client: Arc<MockClient>,
+        mining_mode: MiningMode,
+        state_history: Option<u64>,
+    ) -> Blockchain {
+        Blockchain::new(
+            GAS_PRICE.into(),
+            BLOCK_GAS_LIMIT.into(),
+            mining_mode,
+            QueueLimits::default(),
+            km_client,
+            store,
+            state_history,
+            false,
+            None,
+        )
+        .unwrap()
+    }
+
+    fn new_blockchain(state_history: Option<u64>) -> Blockchain {
+        open_blockchain(
+            Arc::new(MemoryStore::new()),
+            Arc::new(MockClient::new()),
+            MiningMode::Instant,
+            state_history,
+        )
+    }
+
+    /// Account funded in the genesis state.
+    fn funded() -> Address {
+        "b8b3666d8fea887d97ab54f571b8e5020c5c8b58".parse().unwrap()
+    }
+
+    /// Transfer of value between accounts, fake-signed by the sender.
+    fn transfer(from: Address, to: Address, value: U256, nonce: u64) -> SignedTransaction {
+        Transaction {
+            nonce: nonce.into(),
+            gas_price: GAS_PRICE.into(),
+            gas: 21_000.into(),
+            action: Action::Call(to),
+            value,
+            data: vec![],
+        }
+        .fake_sign(from)
+    }
+
+    /// Contract creation with the given init code, fake-signed by the sender.
+    fn create(from: Address, init: Vec<u8>, nonce: u64) -> SignedTransaction {
+        Transaction {
+            nonce: nonce.into(),
+            gas_price: GAS_PRICE.into(),
+            gas: 100_000.into(),
+            action: Action::Create,
+            value: 0.into(),
+            data: init,
+        }
+        .fake_sign(from)
+    }
+
+    #[test]
+    fn test_reopen_disk_store() {
+        let path = std::env::temp_dir().join(format!("oasis-chain-{}", std::process::id()));
+        let km_client = Arc::new(MockClient::new());
+        let open = || {
+            open_blockchain(
+                Arc::new(DiskStore::open(&path).unwrap()),
+                km_client.clone(),
+                MiningMode::Instant,
+                None,
+            )
+        };
+        let recipient = Address::from(0x1000);
+        let txn = transfer(funded(), recipient, 1000.into(), 0);
+        let block_hash = {
+            let blockchain = open();
+            blockchain.mine_block(vec![txn.clone()], false).unwrap();
+            blockchain.get_latest_block().wait().unwrap().hash()
+        };
+
+        // The blocks, receipts and states are all reloaded.
+        let blockchain = open();
+        assert_eq!(blockchain.best_block_number(), 1);
+        assert_eq!(
+            blockchain.get_latest_block().wait().unwrap().hash(),
+            block_hash
+        );
+        let receipt = blockchain
+            .get_txn_receipt_by_hash(txn.hash())
+            .wait()
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.block_hash, block_hash);
+        let balance = |number| {
+            blockchain
+                .state(BlockId::Number(number))
+                .unwrap()
+                .balance(&recipient)
+                .unwrap()
+        };
+        assert_eq!(balance(0), 0.into());
+        assert_eq!(balance(1), 1000.into());
+
+        drop(blockchain);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_multi_transaction_block() {
+        let blockchain = open_blockchain(
+            Arc::new(MemoryStore::new()),
+            Arc::new(MockClient::new()),
+            MiningMode::Interval(Duration::from_secs(3600)),
+            None,
+        );
+
+        // Creations whose init code emits a log: PUSH1 0, PUSH1 0, LOG0.
+        let log = vec![0x60, 0x00, 0x60, 0x00, 0xa0];
+        let txns = vec![
+            create(funded(), log.clone(), 0),
+            transfer(funded(), Address::from(0x1000), 1000.into(), 1),
+            create(funded(), log, 2),
+        ];
+        let results: Vec<_> = txns
+            .iter()
+            .map(|txn| blockchain.submit_transaction(txn.clone()).unwrap().1)
+            .collect();
+        assert_eq!(blockchain.best_block_number(), 0);
+        assert_eq!(blockchain.pending_transactions().len(), 3);
+
+        blockchain.mine_pending().unwrap();
+        assert_eq!(blockchain.best_block_number(), 1);
+        assert!(blockchain.pending_transactions().is_empty());
+        for result in results {
+            assert_eq!(result.wait().unwrap().status_code, 1);
+        }
+
+        let receipts: Vec<LocalizedReceipt> = txns
+            .iter()
+            .map(|txn| {
+                blockchain
+                    .get_txn_receipt_by_hash(txn.hash())
+                    .wait()
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect();
+        let mut cumulative_gas_used = U256::zero();
+        for (index, receipt) in receipts.iter().enumerate() {
+            assert_eq!(receipt.block_number, 1);
+            assert_eq!(receipt.transaction_index, index);
+            cumulative_gas_used = cumulative_gas_used + receipt.gas_used;
+            assert_eq!(receipt.cumulative_gas_used, cumulative_gas_used);
+        }
+
+        // Log indices count across the block.
+        let log_indices: Vec<_> = receipts
+            .iter()
+            .flat_map(|receipt| receipt.logs.iter())
+            .map(|log| {
+                (
+                    log.transaction_index,
+                    log.transaction_log_index,
+                    log.log_index,
+                )
+            })
+            .collect();
+        assert_eq!(log_indices, vec![(0, 0, 0), (2, 0, 1)]);
+    }
+
+    #[test]
+    fn test_release_queued_transaction() {
+        let blockchain = new_blockchain(None);
+        let recipient = Address::from(0x1000);
+
+        // A transaction with a future nonce is held in the queue.
+        let future = transfer(funded(), recipient, 1000.into(), 1);
+        let (_, queued) = blockchain.submit_transaction(future.clone()).unwrap();
+        assert_eq!(blockchain.best_block_number(), 0);
+        assert!(blockchain.get_pending_txn_by_hash(future.hash()).is_some());
+
+        // Filling the gap mines both, in nonce order.
+        let (_, mined) = blockchain
+            .submit_transaction(transfer(funded(), recipient, 1000.into(), 0))
+            .unwrap();
+        assert_eq!(mined.wait().unwrap().status_code, 1);
+        assert_eq!(queued.wait().unwrap().status_code, 1);
+        assert_eq!(blockchain.best_block_number(), 2);
+        assert!(blockchain.get_pending_txn_by_hash(future.hash()).is_none());
+
+        let receipt = blockchain
+            .get_txn_receipt_by_hash(future.hash())
+            .wait()
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.block_number, 2);
+        let state = blockchain.state(BlockId::Latest).unwrap();
+        assert_eq!(state.nonce(&funded()).unwrap(), 2.into());
+        assert_eq!(state.balance(&recipient).unwrap(), 2000.into());
+    }
+
+    #[test]
+    fn test_estimate_gas() {
+        let blockchain = new_blockchain(None);
+        let estimate = |to: Address, gas: u64| {
+            let mut txn = transfer(funded(), to, 0.into(), 0).as_unsigned().clone();
+            txn.gas = gas.into();
+            blockchain
+                .estimate_gas(txn.fake_sign(funded()), BlockNumber::Latest)
+                .wait()
+        };
+        let block_gas_limit = BLOCK_GAS_LIMIT as u64;
+
+        assert_eq!(
+            estimate(Address::from(0x1000), block_gas_limit).unwrap(),
+            21_000.into()
+        );
+
+        // Reverts unless 50000 gas is left: GAS, PUSH2 50000, GT, PUSH1 9,
+        // JUMPI, STOP, JUMPDEST, PUSH1 0, DUP1, REVERT. The search finds the
+        // lowest sufficient gas limit, well above the gas used.
+        let gas_left = Address::from(0x2000);
+        blockchain
+            .set_code(
+                gas_left,
+                vec![
+                    0x5a, 0x61, 0xc3, 0x50, 0x11, 0x60, 0x09, 0x57, 0x00, 0x5b, 0x60, 0x00, 0x80,
+                    0xfd,
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            estimate(gas_left, block_gas_limit).unwrap(),
+            (21_000 + 2 + 50_000).into()
+        );
+
+        // Reverting with all the gas available fails the estimate.
+        let reverts = Address::from(0x3000);
+        blockchain
+            .set_code(reverts, vec![0x60, 0x00, 0x80, 0xfd])
+            .unwrap();
+        match estimate(reverts, block_gas_limit) {
+            Err(EstimateGasError::Execution {
+                error: VmError::Reverted,
+                ..
+            }) => {}
+            result => panic!("unexpected estimate {:?}", result),
+        }
+
+        // Running out of the caller's gas allowance is reported as such: an
+        // infinite loop (JUMPDEST, PUSH1 0, JUMP) and a transfer below the
+        // base gas.
+        let loops = Address::from(0x4000);
+        blockchain
+            .set_code(loops, vec![0x5b, 0x60, 0x00, 0x56])
+            .unwrap();
+        match estimate(loops, 100_000) {
+            Err(EstimateGasError::GasAllowanceExceeded(gas)) => assert_eq!(gas, 100_000.into()),
+            result => panic!("unexpected estimate {:?}", result),
+        }
+        match estimate(Address::from(0x1000), 20_000) {
+            Err(EstimateGasError::GasAllowanceExceeded(gas)) => assert_eq!(gas, 20_000.into()),
+            result => panic!("unexpected estimate {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_historical_state() {
+        let blockchain = new_blockchain(Some(2));
+        let recipient = Address::from(0x1000);
+        for nonce in 0..4 {
+            let txn = transfer(funded(), recipient, 1000.into(), nonce);
+            blockchain.mine_block(vec![txn], false).unwrap();
+        }
+        assert_eq!(blockchain.best_block_number(), 4);
+
+        for number in 2..=4 {
+            let state = blockchain.state(BlockId::Number(number)).unwrap();
+            assert_eq!(state.balance(&recipient).unwrap(), (number * 1000).into());
+        }
+        let state = blockchain.state_at(BlockNumber::Num(3)).unwrap();
+        assert_eq!(state.balance(&recipient).unwrap(), 3000.into());
+
+        // Pruned and future states are refused.
+        let error = |id| blockchain.state(id).err().unwrap().to_string();
+        assert!(error(BlockId::Number(1)).contains("pruned"));
+        assert!(error(BlockId::Earliest).contains("pruned"));
+        assert!(error(BlockId::Number(5)).contains("future"));
+    }
+
+    #[test]
+    fn test_historical_simulation() {
+        let blockchain = new_blockchain(None);
+        let simulate = |txn: &SignedTransaction, number| {
+            blockchain
+                .simulate_transaction(txn.clone(), number, None)
+                .wait()
+        };
+
+        // The sender is only funded as of block 1.
+        let sender = Address::from(0x1000);
+        let txn = transfer(funded(), sender, U256::exp10(18), 0);
+        blockchain.mine_block(vec![txn], false).unwrap();
+        let spend = transfer(sender, Address::from(0x2000), 1.into(), 0);
+        assert!(simulate(&spend, BlockNumber::Num(0)).is_err());
+        assert!(simulate(&spend, BlockNumber::Num(1)).is_ok());
+
+        // Returns the block number: NUMBER, PUSH1 0, MSTORE, PUSH1 32,
+        // PUSH1 0, RETURN. Past blocks are simulated with their own number,
+        // the latest one as if in the next block.
+        let number = Address::from(0x3000);
+        blockchain
+            .set_code(
+                number,
+                vec![0x43, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3],
+            )
+            .unwrap();
+        let call = transfer(funded(), number, 0.into(), 1);
+        let output = |block| simulate(&call, block).unwrap().output;
+        assert!(output(BlockNumber::Num(1)).is_empty());
+        assert_eq!(output(BlockNumber::Num(2)), H256::from(2).to_vec());
+        assert_eq!(output(BlockNumber::Latest), H256::from(3).to_vec());
+    }
+
+    #[test]
+    fn test_trace_dependent_transaction() {
+        let blockchain = new_blockchain(None);
+
+        // The second transaction spends funds received in the first.
+        let sender = Address::from(0x1000);
+        let first = transfer(funded(), sender, U256::exp10(18), 0);
+        let second = transfer(sender, Address::from(0x2000), U256::exp10(17), 0);
+        let results = blockchain
+            .mine_block(vec![first, second.clone()], false)
+            .unwrap();
+        for result in results {
+            assert_eq!(result.unwrap().status_code, 1);
+        }
+
+        let (txn, executed) = blockchain
+            .trace_transaction(second.hash())
+            .wait()
+            .unwrap()
+            .unwrap();
+        assert_eq!(txn.hash(), second.hash());
+        assert!(executed.exception.is_none());
+        assert_eq!(executed.gas_used, 21_000.into());
+    }
+
+    #[test]
+    fn test_set_nonce() {
+        let blockchain = new_blockchain(None);
+        let address = Address::from(0x1000);
+        let key = H256::from(1);
+        let value = H256::from(2);
+        blockchain.set_balance(address, 1000.into()).unwrap();
+        blockchain.set_code(address, vec![0x00]).unwrap();
+        blockchain.set_storage(address, key, value).unwrap();
+        blockchain.set_nonce(address, 10.into()).unwrap();
+
+        let set_nonce = |nonce: U256| {
+            blockchain.set_nonce(address, nonce).unwrap();
+            let state = blockchain.state(BlockId::Latest).unwrap();
+            assert_eq!(state.nonce(&address).unwrap(), nonce);
+
+            // The rest of the account is kept.
+            assert_eq!(state.balance(&address).unwrap(), 1000.into());
+            assert_eq!(*state.code(&address).unwrap().unwrap(), vec![0x00]);
+            assert_eq!(state.storage_at(&address, &key).unwrap(), value);
+        };
+        set_nonce(3.into());
+        set_nonce(U256::exp10(30));
+    }
+
+    #[test]
+    fn test_state_override_nonce() {
+        let blockchain = new_blockchain(None);
+        let address = Address::from(0x1000);
+        blockchain.set_nonce(address, 10.into()).unwrap();
+
+        let mut state = blockchain.state(BlockId::Latest).unwrap();
+        let mut overrides = StateOverride::new();
+        overrides.insert(
+            address.into(),
+            AccountOverride {
+                nonce: Some(U256::from(3).into()),
+                ..Default::default()
+            },
+        );
+        apply_state_override(&mut state, &overrides).unwrap();
+        assert_eq!(state.nonce(&address).unwrap(), 3.into());
+    }
+
+    #[test]
+    fn test_revert_snapshot() {
+        let blockchain = new_blockchain(None);
+        blockchain.increase_time(100);
+        blockchain.impersonate_account(Address::from(1));
+        let clock = blockchain.clock.snapshot();
+        let id = blockchain.snapshot().unwrap();
+
+        blockchain.mine(None).unwrap();
+        blockchain.increase_time(50);
+        blockchain.impersonate_account(Address::from(2));
+        blockchain.stop_impersonating_account(&Address::from(1));
+
+        assert!(blockchain.revert(id).unwrap());
+        assert_eq!(blockchain.best_block_number(), 0);
+        assert_eq!(blockchain.clock.snapshot(), clock);
+        assert!(blockchain.is_impersonated(&Address::from(1)));
+        assert!(!blockchain.is_impersonated(&Address::from(2)));
+
+        // The snapshot is discarded.
+        assert!(!blockchain.revert(id).unwrap());
+    }
+
+    #[test]
+    fn test_migrate_storage_keys() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let km_client = Arc::new(MockClient::new());
+        let open = || open_blockchain(store.clone(), km_client.clone(), MiningMode::Instant, None);
+        open();
+
+        // Turn the chain into one with a storage slot written by the legacy
+        // scheme, which block 0 wrote.
+        let address = Address::from(0x1000);
+        let contract_id = ContractId::from(&keccak(address.to_vec())[..]);
+        let contract_key = km_client.get_or_create_keys(contract_id).unwrap();
+        let mut key = [0u8; KEY_SIZE];
+        key.copy_from_slice(&contract_key.state_key.as_ref()[..KEY_SIZE]);
+        let d2 = DeoxysII::new(&key);
+        let legacy_key = d2.seal(&[0u8; NONCE_SIZE], vec![1; 32], vec![]);
+        let nonce = [7u8; NONCE_SIZE];
+        let mut legacy_value = d2.seal(&nonce, vec![2; 32], legacy_key.clone());
+        legacy_value.extend_from_slice(&nonce);
+        let mut legacy_slot = address.to_vec();
+        legacy_slot.extend_from_slice(&legacy_key);
+
+        let mut batch = WriteBatch::new();
+        storage::put_state_version(&mut batch, &legacy_slot, 0, &Some(legacy_value));
+        let mut journal = RlpStream::new_list(1);
+        journal.append(&legacy_slot);
+        batch.put(Column::StateJournal, &0u64.to_be_bytes(), &journal.out());
+        batch.delete(Column::Extra, DETERMINISTIC_STORAGE_KEYS_KEY);
+        store.write(batch).unwrap();
+
+        // Reopening re-encrypts the slot under its deterministic key.
+        let blockchain = open();
+        let mut ctx = ConfidentialCtx::new(H256::default(), km_client.clone());
+        ctx.activate(Some(address)).unwrap();
+        let key = ctx.encrypt_storage_key(vec![1; 32]).unwrap();
+        let mut slot = address.to_vec();
+        slot.extend_from_slice(&key);
+
+        let mkvs = blockchain.chain_state.read().unwrap().mkvs_at(0);
+        assert_eq!(mkvs.get(&legacy_slot), None);
+        let value = mkvs.get(&slot).unwrap();
+        assert_eq!(ctx.decrypt_storage_value(key, value).unwrap(), vec![2; 32]);
+
+        let journal = store
+            .get(Column::StateJournal, &0u64.to_be_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(Rlp::new(&journal).val_at::<Vec<u8>>(0).unwrap(), slot);
+        assert!(store
+            .get(Column::Extra, DETERMINISTIC_STORAGE_KEYS_KEY)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_snapshot_with_state_history() {
+        let blockchain = new_blockchain(Some(10));
+        assert!(blockchain.snapshot().is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! Controllable chain clock.
+use std::sync::Mutex;
+
+use crate::util;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Inner {
+    /// Offset from the system time, in seconds.
+    offset: i64,
+    /// Timestamp to use for the next block, if pinned.
+    next_block_timestamp: Option<u64>,
+}
+
+/// Clock used to timestamp blocks.
+///
+/// Follows the system time, shifted by an offset which can be increased to
+/// move the chain forward in time. The timestamp of the next block can also
+/// be pinned, in which case the clock continues from that timestamp once the
+/// block has been mined.
+#[derive(Default)]
+pub struct Clock {
+    inner: Mutex<Inner>,
+}
+
+/// Saved offset and pinned timestamp of a clock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClockSnapshot(Inner);
+
+impl Clock {
+    /// Current chain time (Unix timestamp).
+    pub fn now(&self) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        Self::shifted(inner.offset)
+    }
+
+    /// Move the chain time forward by the given number of seconds.
+    ///
+    /// Returns the total offset from the system time, in seconds.
+    pub fn increase_time(&self, seconds: u64) -> i64 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.offset = inner.offset.saturating_add(seconds as i64);
+        inner.offset
+    }
+
+    /// Pin the timestamp of the next block.
+    pub fn set_next_block_timestamp(&self, timestamp: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_block_timestamp = Some(timestamp);
+    }
+
+    /// Timestamp the next block would have if it was mined now.
+    pub fn next_block_timestamp(&self) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .next_block_timestamp
+            .unwrap_or_else(|| Self::shifted(inner.offset))
+    }
+
+    /// Timestamp for a block which is being mined.
+    ///
+    /// If the timestamp was pinned, the pin is cleared and the offset
+    /// adjusted so that the chain time continues from it.
+    pub fn take_block_timestamp(&self) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.next_block_timestamp.take() {
+            Some(timestamp) => {
+                inner.offset = timestamp as i64 - util::get_timestamp() as i64;
+                timestamp
+            }
+            None => Self::shifted(inner.offset),
+        }
+    }
+
+    /// Save the offset and pinned timestamp of the clock.
+    pub fn snapshot(&self) -> ClockSnapshot {
+        ClockSnapshot(self.inner.lock().unwrap().clone())
+    }
+
+    /// Restore the offset and pinned timestamp saved in a snapshot.
+    pub fn restore(&self, snapshot: ClockSnapshot) {
+        *self.inner.lock().unwrap() = snapshot.0;
+    }
+
+    fn shifted(offset: i64) -> u64 {
+        (util::get_timestamp() as i64).saturating_add(offset).max(0) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_increase_time() {
+        let clock = Clock::default();
+        let before = util::get_timestamp();
+
+        assert_eq!(clock.increase_time(100), 100);
+        assert_eq!(clock.increase_time(50), 150);
+        assert!(clock.now() >= before + 150);
+        assert!(clock.take_block_timestamp() >= before + 150);
+    }
+
+    #[test]
+    fn test_next_block_timestamp() {
+        let clock = Clock::default();
+        let pinned = util::get_timestamp() + 1000;
+
+        clock.set_next_block_timestamp(pinned);
+        assert_eq!(clock.next_block_timestamp(), pinned);
+        assert_eq!(clock.next_block_timestamp(), pinned);
+        assert_eq!(clock.take_block_timestamp(), pinned);
+
+        // The clock continues from the pinned timestamp.
+        let next = clock.next_block_timestamp();
+        assert!(next >= pinned && next < pinned + 10);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let clock = Clock::default();
+        let pinned = util::get_timestamp() + 1000;
+        clock.increase_time(100);
+        clock.set_next_block_timestamp(pinned);
+        let snapshot = clock.snapshot();
+
+        clock.take_block_timestamp();
+        clock.increase_time(50);
+        assert_ne!(clock.snapshot(), snapshot);
+
+        clock.restore(snapshot.clone());
+        assert_eq!(clock.snapshot(), snapshot);
+        assert_eq!(clock.next_block_timestamp(), pinned);
+    }
+}
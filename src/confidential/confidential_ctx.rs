@@ -4,16 +4,19 @@ use ekiden_crypto::{
     hash::Hash,
     mrae::{
         deoxysii::{DeoxysII, KEY_SIZE, TAG_SIZE},
+        deterministic::DeterministicCipher,
         nonce::{Nonce, NONCE_SIZE, TAG_SIZE as NONCE_TAG_SIZE},
     },
 };
-use ekiden_keymanager::{client::MockClient, ContractId, ContractKey, PublicKey};
-use ethcore::vm::{AuthenticatedPayload, ConfidentialCtx as EthConfidentialCtx, Error, Result};
+use ekiden_keymanager::{
+    client::KeyManagerClient, ContractId, ContractKey, Epoch, InputKeyPair, PublicKey,
+};
+use ethcore::vm::{AuthenticatedPayload, ConfidentialCtx as EthConfidentialCtx, Result};
 use ethereum_types::{Address, H256};
 use hash::keccak;
 use zeroize::Zeroize;
 
-use super::crypto;
+use super::{crypto, ConfidentialError};
 
 /// Facade for the underlying confidential contract services to be injected into
 /// the parity state. Manages the confidential state--i.e., encryption keys and
@@ -39,29 +42,47 @@ pub struct ConfidentialCtx {
     prev_block_hash: H256,
     /// Deoxys-II instance used for encrypting and decrypting contract storage.
     d2: Option<DeoxysII>,
+    /// Cipher used for deterministically encrypting contract storage keys.
+    key_cipher: Option<DeterministicCipher>,
     /// The next nonce to use when encrypting a storage value. When we start
     /// executing a confidential transaction, its value is set to
     /// H(prev_block_hash || contract_address)[:11] || 0x00000000. The value is
     /// incremented after each encrypt operation.
     next_storage_nonce: Option<Nonce>,
     /// Key manager client.
-    key_manager: Arc<MockClient>,
+    key_manager: Arc<dyn KeyManagerClient>,
+    /// Epochs whose ephemeral keys are accepted to decrypt transactions,
+    /// most recent first. If empty, the long-term keys are used.
+    input_epochs: Vec<Epoch>,
+    /// The contract address and the input key pair with which the session
+    /// was decrypted, used to encrypt messages back to the peer.
+    session_keys: Option<(Address, InputKeyPair)>,
 }
 
 impl ConfidentialCtx {
-    pub fn new(prev_block_hash: H256, key_manager: Arc<MockClient>) -> Self {
+    pub fn new(prev_block_hash: H256, key_manager: Arc<dyn KeyManagerClient>) -> Self {
         Self {
             peer_public_key: None,
             contract: None,
             next_nonce: None,
             activated: false,
             d2: None,
+            key_cipher: None,
             prev_block_hash,
             next_storage_nonce: None,
             key_manager,
+            input_epochs: vec![],
+            session_keys: None,
         }
     }
 
+    /// Use the ephemeral keys of the given epochs, most recent first, rather
+    /// than the long-term keys to decrypt transactions.
+    pub fn with_input_epochs(mut self, input_epochs: Vec<Epoch>) -> Self {
+        self.input_epochs = input_epochs;
+        self
+    }
+
     /// Constructor to be used for testing only.
     #[cfg(feature = "test")]
     pub fn new_test(
@@ -72,7 +93,7 @@ impl ConfidentialCtx {
         prev_block_hash: H256,
         d2: Option<DeoxysII>,
         next_storage_nonce: Option<Nonce>,
-        key_manager: Arc<KeyManagerClient>,
+        key_manager: Arc<dyn KeyManagerClient>,
     ) -> Self {
         Self {
             peer_public_key,
@@ -80,20 +101,23 @@ impl ConfidentialCtx {
             next_nonce,
             activated,
             d2,
+            key_cipher: None,
             prev_block_hash,
             next_storage_nonce,
             key_manager,
+            input_epochs: vec![],
+            session_keys: None,
         }
     }
 
     #[cfg(test)]
     pub fn decrypt(&self, encrypted_tx_data: Vec<u8>) -> Result<Vec<u8>> {
-        if self.contract.is_none() {
-            return Err(Error::Confidential("The confidential context must have a contract key when opening encrypted transaction data".to_string()));
-        }
-        let contract_secret_key = self.contract.as_ref().unwrap().1.input_keypair.get_sk();
-        let decryption = crypto::decrypt(Some(encrypted_tx_data), contract_secret_key)
-            .map_err(|err| Error::Confidential(err.to_string()))?;
+        let (_, contract_key) = self
+            .contract
+            .as_ref()
+            .ok_or(ConfidentialError::MissingContractKey)?;
+        let contract_secret_key = contract_key.input_keypair.get_sk();
+        let decryption = crypto::decrypt(Some(encrypted_tx_data), contract_secret_key)?;
 
         Ok(decryption.plaintext)
     }
@@ -102,15 +126,21 @@ impl ConfidentialCtx {
         let old_contract_address = self.contract.as_ref().map(|c| c.0);
         self.contract = contract;
 
-        // If this is a confidential contract, initialize Deoxys-II instance.
-        self.d2 = self.contract.as_ref().map(|c| {
+        // If this is a confidential contract, initialize Deoxys-II instances.
+        let ciphers = self.contract.as_ref().map(|c| {
             let state_key = c.1.state_key;
             let mut key = [0u8; KEY_SIZE];
             key.copy_from_slice(&state_key.as_ref()[..KEY_SIZE]);
-            let d2 = DeoxysII::new(&key);
+            let ciphers = (DeoxysII::new(&key), DeterministicCipher::new(&key));
             key.zeroize();
-            d2
+            ciphers
         });
+        let (d2, key_cipher) = match ciphers {
+            Some((d2, key_cipher)) => (Some(d2), Some(key_cipher)),
+            None => (None, None),
+        };
+        self.d2 = d2;
+        self.key_cipher = key_cipher;
 
         // Storage encryption nonce <- H(prev_block_hash || address)[:11] || 0x00000000
         self.next_storage_nonce = self.contract.as_ref().map(|c| {
@@ -126,6 +156,46 @@ impl ConfidentialCtx {
 
         old_contract_address
     }
+
+    /// Input key pairs of the current contract with which to try decrypting
+    /// a transaction, in order.
+    fn input_keypairs(&self) -> Result<Vec<InputKeyPair>> {
+        let (address, contract_key) = self
+            .contract
+            .as_ref()
+            .ok_or(ConfidentialError::MissingContractKey)?;
+        if self.input_epochs.is_empty() {
+            return Ok(vec![contract_key.input_keypair.clone()]);
+        }
+
+        let contract_id = ContractId::from(&keccak(address.to_vec())[..]);
+        self.input_epochs
+            .iter()
+            .map(|epoch| {
+                self.key_manager
+                    .get_or_create_ephemeral_keys(contract_id, *epoch)
+                    .map_err(|err| ConfidentialError::KeyManager(err.to_string()).into())
+            })
+            .collect()
+    }
+
+    /// Input key pair of the current contract with which to encrypt messages
+    /// to the peer, i.e., the key pair with which the session was decrypted
+    /// if it was for this contract, or else the most recent one.
+    fn session_keypair(&self) -> Result<InputKeyPair> {
+        match (&self.session_keys, &self.contract) {
+            (Some((session_address, keypair)), Some((address, _)))
+                if session_address == address =>
+            {
+                Ok(keypair.clone())
+            }
+            _ => Ok(self
+                .input_keypairs()?
+                .into_iter()
+                .next()
+                .ok_or(ConfidentialError::MissingContractKey)?),
+        }
+    }
 }
 
 impl EthConfidentialCtx for ConfidentialCtx {
@@ -148,7 +218,10 @@ impl EthConfidentialCtx for ConfidentialCtx {
             None => Ok(self.swap_contract(None)),
             Some(contract) => {
                 let contract_id = ContractId::from(&keccak(contract.to_vec())[..]);
-                let contract_key = self.key_manager.get_or_create_keys(contract_id);
+                let contract_key = self
+                    .key_manager
+                    .get_or_create_keys(contract_id)
+                    .map_err(|err| ConfidentialError::KeyManager(err.to_string()))?;
 
                 Ok(self.swap_contract(Some((contract, contract_key))))
             }
@@ -157,65 +230,80 @@ impl EthConfidentialCtx for ConfidentialCtx {
 
     fn deactivate(&mut self) {
         self.peer_public_key = None;
+        self.session_keys = None;
         self.contract = None;
         self.next_nonce = None;
         self.activated = false;
         self.d2 = None;
+        self.key_cipher = None;
         self.next_storage_nonce = None;
     }
 
     fn encrypt_session(&mut self, data: Vec<u8>) -> Result<Vec<u8>> {
-        let mut err_msg = "".to_string();
+        let mut missing = vec![];
         if self.peer_public_key.is_none() {
-            err_msg.push_str("; no peer public key specified");
+            missing.push("no peer public key specified");
         }
         if self.contract.is_none() {
-            err_msg.push_str("; no contract specified from which to extract contract public key");
+            missing.push("no contract specified from which to extract contract public key");
         }
         if self.next_nonce.is_none() {
-            err_msg.push_str("; no next nonce specified");
-        }
-        if !err_msg.is_empty() {
-            return Err(Error::Confidential(format!(
-                "Cannot encrypt session {}.",
-                err_msg
-            )));
+            missing.push("no next nonce specified");
         }
+        let (peer_public_key, nonce) = match (&self.peer_public_key, &self.next_nonce) {
+            (Some(peer_public_key), Some(nonce)) if missing.is_empty() => {
+                (peer_public_key.clone(), nonce.clone())
+            }
+            _ => return Err(ConfidentialError::MissingSession(missing.join("; ")).into()),
+        };
 
-        let contract_key = &self.contract.as_ref().unwrap().1;
-        let contract_pk = contract_key.input_keypair.get_pk();
-        let contract_sk = contract_key.input_keypair.get_sk();
+        let keypair = self.session_keypair()?;
+        let contract_pk = keypair.get_pk();
+        let contract_sk = keypair.get_sk();
 
         let encrypted_payload = crypto::encrypt(
             data,
-            self.next_nonce.clone().unwrap(),
-            self.peer_public_key.clone().unwrap(),
+            nonce.clone(),
+            peer_public_key,
             contract_pk,
             contract_sk,
             vec![],
-        )
-        .map_err(|err| Error::Confidential(err.to_string()))?;
+        )?;
 
-        self.next_nonce
-            .as_mut()
-            .unwrap()
+        let mut nonce = nonce;
+        nonce
             .increment()
-            .map_err(|err| Error::Confidential(err.to_string()))?;
+            .map_err(|_| ConfidentialError::NonceExhausted)?;
+        self.next_nonce = Some(nonce);
 
         Ok(encrypted_payload)
     }
 
     fn decrypt_session(&mut self, encrypted_payload: Vec<u8>) -> Result<AuthenticatedPayload> {
-        let contract_secret_key = self.contract.as_ref().unwrap().1.input_keypair.get_sk();
-
-        let decryption = crypto::decrypt(Some(encrypted_payload), contract_secret_key)
-            .map_err(|err| Error::Confidential(err.to_string()))?;
+        let address = self
+            .contract
+            .as_ref()
+            .map(|c| c.0)
+            .ok_or(ConfidentialError::MissingContractKey)?;
+
+        // Try each accepted key pair in turn; decryption fails
+        // authentication under the wrong key.
+        let mut result = Err(ConfidentialError::MissingContractKey);
+        for keypair in self.input_keypairs()? {
+            result = crypto::decrypt(Some(encrypted_payload.clone()), keypair.get_sk())
+                .map(|decryption| (decryption, keypair));
+            if result.is_ok() {
+                break;
+            }
+        }
+        let (decryption, keypair) = result?;
         self.peer_public_key = Some(decryption.peer_public_key);
+        self.session_keys = Some((address, keypair));
 
         let mut nonce = decryption.nonce;
         nonce
             .increment()
-            .map_err(|err| Error::Confidential(err.to_string()))?;
+            .map_err(|_| ConfidentialError::NonceExhausted)?;
         self.next_nonce = Some(nonce);
 
         Ok(AuthenticatedPayload {
@@ -225,43 +313,40 @@ impl EthConfidentialCtx for ConfidentialCtx {
     }
 
     fn encrypt_storage_key(&self, data: Vec<u8>) -> Result<Vec<u8>> {
-        // TODO: Use AES-ECB rather than Deoxys-II with a 0 nonce.
-        let nonce = [0u8; NONCE_SIZE];
-        Ok(self
-            .d2
+        let key_cipher = self
+            .key_cipher
             .as_ref()
-            .expect("Should always have a Deoxys-II instance to encrypt storage")
-            .seal(&nonce, data, vec![]))
+            .ok_or(ConfidentialError::MissingContractKey)?;
+        Ok(key_cipher.seal(data, vec![]))
     }
 
     fn encrypt_storage_value(&mut self, storage_key: Vec<u8>, data: Vec<u8>) -> Result<Vec<u8>> {
+        let (d2, next_storage_nonce) = match (&self.d2, &mut self.next_storage_nonce) {
+            (Some(d2), Some(next_storage_nonce)) => (d2, next_storage_nonce),
+            _ => return Err(ConfidentialError::MissingContractKey.into()),
+        };
         let mut nonce = [0u8; NONCE_SIZE];
-        nonce.copy_from_slice(
-            &self
-                .next_storage_nonce
-                .as_ref()
-                .expect("Should always have a storage encryption nonce.")[..NONCE_SIZE],
-        );
+        nonce.copy_from_slice(&next_storage_nonce[..NONCE_SIZE]);
 
-        let mut ciphertext = self
-            .d2
-            .as_ref()
-            .expect("Should always have a Deoxys-II instance to encrypt storage")
-            .seal(&nonce, data, storage_key);
+        let mut ciphertext = d2.seal(&nonce, data, storage_key);
         ciphertext.extend_from_slice(&nonce); // ciphertext || tag || nonce
 
-        self.next_storage_nonce
-            .as_mut()
-            .unwrap()
+        next_storage_nonce
             .increment()
-            .map_err(|err| Error::Confidential(err.to_string()))?;
+            .map_err(|_| ConfidentialError::NonceExhausted)?;
 
         Ok(ciphertext)
     }
 
     fn decrypt_storage_value(&self, storage_key: Vec<u8>, data: Vec<u8>) -> Result<Vec<u8>> {
+        let d2 = self
+            .d2
+            .as_ref()
+            .ok_or(ConfidentialError::MissingContractKey)?;
         if data.len() < TAG_SIZE + NONCE_SIZE {
-            return Err(Error::Confidential("truncated ciphertext".to_string()));
+            return Err(
+                ConfidentialError::CorruptedStorage("truncated ciphertext".to_string()).into(),
+            );
         }
 
         // Split out the nonce from the tail of ciphertext || tag || nonce.
@@ -270,12 +355,8 @@ impl EthConfidentialCtx for ConfidentialCtx {
         nonce.copy_from_slice(&data[nonce_offset..]);
         let ciphertext = &data[..nonce_offset];
 
-        Ok(self
-            .d2
-            .as_ref()
-            .expect("Should always have a Deoxys-II instance to decrypt storage")
-            .open(&nonce, ciphertext.to_vec(), storage_key)
-            .unwrap())
+        d2.open(&nonce, ciphertext.to_vec(), storage_key)
+            .map_err(|err| ConfidentialError::CorruptedStorage(err.to_string()).into())
     }
 
     fn peer(&self) -> Option<Vec<u8>> {
@@ -285,7 +366,8 @@ impl EthConfidentialCtx for ConfidentialCtx {
 
 #[cfg(test)]
 mod tests {
-    use ekiden_keymanager::{ContractKey, PrivateKey, PublicKey, StateKey};
+    use ekiden_keymanager::{client::MockClient, ContractKey, PrivateKey, PublicKey, StateKey};
+    use ethcore::vm::Error as VmError;
 
     use super::*;
 
@@ -296,7 +378,7 @@ mod tests {
 
         assert_eq!(
             &format!("{}", res.err().unwrap()),
-            "Confidential error: The confidential context must have a contract key when opening encrypted transaction data"
+            "Confidential error: missing contract key"
         );
     }
 
@@ -315,9 +397,12 @@ mod tests {
             next_nonce: Some(nonce.clone()),
             prev_block_hash: H256::default(),
             next_storage_nonce: Some(nonce),
-            // No storage encryption, so don't need a Deoxys-II instance.
+            // No storage encryption, so don't need Deoxys-II instances.
             d2: None,
+            key_cipher: None,
             key_manager: Arc::new(MockClient::new()),
+            input_epochs: vec![],
+            session_keys: None,
             activated: true,
         };
 
@@ -325,7 +410,7 @@ mod tests {
 
         assert_eq!(
             &format!("{}", res.err().unwrap()),
-            "Confidential error: invalid nonce or public key"
+            "Confidential error: invalid encrypted payload: invalid nonce or public key"
         );
     }
 
@@ -345,9 +430,12 @@ mod tests {
                 next_nonce: Some(nonce),
                 prev_block_hash: H256::default(),
                 next_storage_nonce: None,
-                // No storage encryption, so don't need a Deoxys-II instance.
+                // No storage encryption, so don't need Deoxys-II instances.
                 d2: None,
+                key_cipher: None,
                 key_manager: Arc::new(MockClient::new()),
+                input_epochs: vec![],
+                session_keys: None,
                 activated: true,
             }
             .activated(),
@@ -360,9 +448,12 @@ mod tests {
                 next_nonce: None,
                 prev_block_hash: H256::default(),
                 next_storage_nonce: None,
-                // No storage encryption, so don't need a Deoxys-II instance.
+                // No storage encryption, so don't need Deoxys-II instances.
                 d2: None,
+                key_cipher: None,
                 key_manager: Arc::new(MockClient::new()),
+                input_epochs: vec![],
+                session_keys: None,
                 activated: false,
             }
             .activated(),
@@ -385,9 +476,12 @@ mod tests {
             next_nonce: Some(nonce),
             prev_block_hash: H256::default(),
             next_storage_nonce: None,
-            // No storage encryption, so don't need a Deoxys-II instance.
+            // No storage encryption, so don't need Deoxys-II instances.
             d2: None,
+            key_cipher: None,
             key_manager: Arc::new(MockClient::new()),
+            input_epochs: vec![],
+            session_keys: None,
             activated: false,
         };
 
@@ -396,7 +490,138 @@ mod tests {
 
         assert_eq!(
             &format!("{}", res.err().unwrap()),
-            "Confidential error: The confidential context must have a contract key when opening encrypted transaction data"
+            "Confidential error: missing contract key"
         );
     }
+
+    #[test]
+    fn test_decrypt_session_with_ephemeral_keys() {
+        let key_manager = Arc::new(MockClient::new());
+        let address = Address::from(1);
+        let contract_id = ContractId::from(&keccak(address.to_vec())[..]);
+        let (peer_pk, peer_sk) = ekiden_crypto::mrae::deoxysii::generate_key_pair();
+
+        // Encrypt to the ephemeral key of epoch 1.
+        let contract_pk = key_manager
+            .get_or_create_ephemeral_keys(contract_id, 1)
+            .unwrap()
+            .get_pk();
+        let payload = crypto::encrypt(
+            b"data".to_vec(),
+            Nonce::new([0; NONCE_SIZE]),
+            contract_pk,
+            PublicKey(peer_pk),
+            PrivateKey(peer_sk),
+            vec![],
+        )
+        .unwrap();
+
+        let decrypt = |input_epochs: Vec<Epoch>| {
+            let mut ctx = ConfidentialCtx::new(H256::default(), key_manager.clone())
+                .with_input_epochs(input_epochs);
+            ctx.activate(Some(address)).unwrap();
+            ctx.decrypt_session(payload.clone())
+                .map(|payload| payload.decrypted_data)
+        };
+
+        // Accepted in epoch 1, and in epoch 2 during the grace window.
+        assert_eq!(decrypt(vec![1]).unwrap(), b"data".to_vec());
+        assert_eq!(decrypt(vec![2, 1]).unwrap(), b"data".to_vec());
+
+        // Rejected afterwards, and under the long-term key.
+        assert!(decrypt(vec![2]).is_err());
+        assert!(decrypt(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_encrypt_storage_key() {
+        let key_manager = Arc::new(MockClient::new());
+        let address = Address::from(1);
+        let encrypt = || {
+            let mut ctx = ConfidentialCtx::new(H256::default(), key_manager.clone());
+            ctx.activate(Some(address)).unwrap();
+            ctx.encrypt_storage_key(vec![1; 32]).unwrap()
+        };
+
+        // Storage keys are encrypted deterministically.
+        let key = encrypt();
+        assert_eq!(key, encrypt());
+        assert_eq!(key.len(), 32 + TAG_SIZE);
+
+        // They are not encrypted under the state key itself.
+        let contract_id = ContractId::from(&keccak(address.to_vec())[..]);
+        let state_key = key_manager
+            .get_or_create_keys(contract_id)
+            .unwrap()
+            .state_key;
+        let mut d2_key = [0u8; KEY_SIZE];
+        d2_key.copy_from_slice(&state_key.as_ref()[..KEY_SIZE]);
+        let state_key_seal = DeoxysII::new(&d2_key).seal(&[0u8; NONCE_SIZE], vec![1; 32], vec![]);
+        assert_ne!(key, state_key_seal);
+    }
+
+    /// Code of a confidential error returned by the context.
+    fn code_of(err: &VmError) -> Option<i64> {
+        match err {
+            VmError::Confidential(message) => ConfidentialError::code_of(message),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_decrypt_session_before_activate() {
+        let mut ctx = ConfidentialCtx::new(H256::default(), Arc::new(MockClient::new()));
+        let err = ctx.decrypt_session(vec![0; 100]).unwrap_err();
+        assert_eq!(
+            code_of(&err),
+            Some(ConfidentialError::MissingContractKey.code())
+        );
+        assert!(ctx.encrypt_session(vec![]).is_err());
+        assert!(ctx.encrypt_storage_key(vec![]).is_err());
+        assert!(ctx.encrypt_storage_value(vec![], vec![]).is_err());
+        assert!(ctx.decrypt_storage_value(vec![], vec![0; 100]).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_storage_value_random() {
+        let mut ctx = ConfidentialCtx::new(H256::default(), Arc::new(MockClient::new()));
+        ctx.activate(Some(Address::from(1))).unwrap();
+        let key = ctx.encrypt_storage_key(vec![1; 32]).unwrap();
+        let value = ctx.encrypt_storage_value(key.clone(), vec![2; 32]).unwrap();
+        assert_eq!(
+            ctx.decrypt_storage_value(key.clone(), value.clone())
+                .unwrap(),
+            vec![2; 32]
+        );
+
+        let corrupted_storage = Some(ConfidentialError::CorruptedStorage(String::new()).code());
+        let decrypt = |data: Vec<u8>| {
+            let err = ctx.decrypt_storage_value(key.clone(), data).unwrap_err();
+            assert_eq!(code_of(&err), corrupted_storage);
+        };
+
+        // Every flipped bit is detected.
+        for i in 0..value.len() * 8 {
+            let mut tampered = value.clone();
+            tampered[i / 8] ^= 1 << (i % 8);
+            decrypt(tampered);
+        }
+
+        // Truncated and random values (xorshift64) fail to decrypt.
+        for len in 0..value.len() {
+            decrypt(value[..len].to_vec());
+        }
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        for i in 0..1_000 {
+            let data = (0..i % 100)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            decrypt(data);
+        }
+    }
 }
@@ -10,7 +10,8 @@ use ekiden_crypto::mrae::{
     nonce::{Nonce, NONCE_SIZE},
 };
 use ekiden_keymanager::{PrivateKey, PublicKey};
-use failure::{format_err, Fallible, ResultExt};
+
+use super::ConfidentialError;
 
 /// Number of bytes representing the CIPHER_LEN parameter of the confidential
 /// wire format.
@@ -34,30 +35,37 @@ pub fn encrypt(
     public_key: PublicKey,
     secret_key: PrivateKey,
     aad: Vec<u8>,
-) -> Fallible<Vec<u8>> {
+) -> Result<Vec<u8>, ConfidentialError> {
     let ciphertext = deoxysii::box_seal(
         &nonce.clone(),
         plaintext.clone(),
         aad.clone(),
         &peer_public_key.into(),
         &secret_key.into(),
-    )?;
+    )
+    .map_err(|err| ConfidentialError::EncryptionFailed(err.to_string()))?;
     Ok(encode_encryption(ciphertext, nonce, public_key, aad))
 }
 
 /// Decrypts the given payload generated in the same manner by the encrypt method.
 /// extracts the nonce and public key and uses them along with the given secret_key
 /// to decrypt the cipher, returning the resulting Decryption struct.
-pub fn decrypt(data: Option<Vec<u8>>, secret_key: PrivateKey) -> Fallible<Decryption> {
-    if data.is_none() {
-        return Ok(Decryption {
-            plaintext: Default::default(),
-            peer_public_key: Default::default(),
-            nonce: Nonce::new([0; NONCE_SIZE]),
-            aad: Default::default(),
-        });
-    }
-    let (peer_public_key, _, _, cipher, aad, nonce) = split_encrypted_payload(data.unwrap())?;
+pub fn decrypt(
+    data: Option<Vec<u8>>,
+    secret_key: PrivateKey,
+) -> Result<Decryption, ConfidentialError> {
+    let data = match data {
+        Some(data) => data,
+        None => {
+            return Ok(Decryption {
+                plaintext: Default::default(),
+                peer_public_key: Default::default(),
+                nonce: Nonce::new([0; NONCE_SIZE]),
+                aad: Default::default(),
+            })
+        }
+    };
+    let (peer_public_key, _, _, cipher, aad, nonce) = split_encrypted_payload(data)?;
     let plaintext = deoxysii::box_open(
         &nonce,
         cipher,
@@ -65,7 +73,7 @@ pub fn decrypt(data: Option<Vec<u8>>, secret_key: PrivateKey) -> Fallible<Decryp
         &peer_public_key.into(),
         &secret_key.into(),
     )
-    .with_context(|e| format!("payload open failed: {}", e))?;
+    .map_err(|_| ConfidentialError::PayloadOpenFailed)?;
     Ok(Decryption {
         plaintext,
         peer_public_key,
@@ -108,10 +116,14 @@ fn encode_encryption(
 /// Returns a tuple of each component.
 fn split_encrypted_payload(
     data: Vec<u8>,
-) -> Fallible<(PublicKey, u64, u64, Vec<u8>, Vec<u8>, Nonce)> {
+) -> Result<(PublicKey, u64, u64, Vec<u8>, Vec<u8>, Nonce), ConfidentialError> {
     if data.len() < PublicKey::len() + NONCE_SIZE + CIPHER_LEN_SIZE + AAD_LEN_SIZE {
-        return Err(format_err!("invalid nonce or public key"));
+        return Err(ConfidentialError::InvalidPayload(
+            "invalid nonce or public key".to_string(),
+        ));
     }
+    let invalid_size =
+        || ConfidentialError::InvalidPayload("invalid size for ciphertext".to_string());
 
     let peer_public_key = PublicKey::from(&data[..PublicKey::len()]);
 
@@ -119,18 +131,27 @@ fn split_encrypted_payload(
     let cipher_len_end = cipher_len_start + CIPHER_LEN_SIZE;
     let mut cipher_array = [0u8; 8];
     cipher_array.copy_from_slice(&data[cipher_len_start..cipher_len_end]);
-    let cipher_len: usize = u64::from_le_bytes(cipher_array).try_into()?;
+    let cipher_len: usize = u64::from_le_bytes(cipher_array)
+        .try_into()
+        .map_err(|_| invalid_size())?;
 
     let aad_len_start = cipher_len_end;
     let aad_len_end = aad_len_start + AAD_LEN_SIZE;
     let mut aad_array = [0u8; 8];
     aad_array.copy_from_slice(&data[aad_len_start..aad_len_end]);
-    let aad_len: usize = u64::from_le_bytes(aad_array).try_into()?;
-
-    let expected_data_length =
-        PublicKey::len() + CIPHER_LEN_SIZE + AAD_LEN_SIZE + cipher_len + aad_len + NONCE_SIZE;
+    let aad_len: usize = u64::from_le_bytes(aad_array)
+        .try_into()
+        .map_err(|_| invalid_size())?;
+
+    // The lengths are untrusted, so guard against overflow.
+    let expected_data_length = cipher_len
+        .checked_add(aad_len)
+        .and_then(|len| {
+            len.checked_add(PublicKey::len() + CIPHER_LEN_SIZE + AAD_LEN_SIZE + NONCE_SIZE)
+        })
+        .ok_or_else(invalid_size)?;
     if data.len() != expected_data_length {
-        return Err(format_err!("invalid size for ciphertext"));
+        return Err(invalid_size());
     }
 
     let cipher_start = aad_len_end;
@@ -156,3 +177,70 @@ fn split_encrypted_payload(
         nonce,
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random bytes (xorshift64), for fuzz-style tests.
+    fn random_bytes(state: &mut u64, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                *state ^= *state << 13;
+                *state ^= *state >> 7;
+                *state ^= *state << 17;
+                *state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_encrypted_payload_random() {
+        let header_len = PublicKey::len() + CIPHER_LEN_SIZE + AAD_LEN_SIZE;
+        let mut state = 0x2545_f491_4f6c_dd1d;
+        for i in 0..10_000 {
+            let len = (i % 160) as usize;
+            let mut data = random_bytes(&mut state, len);
+
+            // Random lengths rarely match, so make some of them consistent.
+            if i % 2 == 0 && len >= header_len + NONCE_SIZE {
+                let cipher_len = (len - header_len - NONCE_SIZE) as u64 / 2;
+                let aad_len = (len - header_len - NONCE_SIZE) as u64 - cipher_len;
+                data[PublicKey::len()..PublicKey::len() + CIPHER_LEN_SIZE]
+                    .copy_from_slice(&cipher_len.to_le_bytes());
+                data[PublicKey::len() + CIPHER_LEN_SIZE..header_len]
+                    .copy_from_slice(&aad_len.to_le_bytes());
+            }
+
+            match split_encrypted_payload(data.clone()) {
+                Ok((_, cipher_len, aad_len, cipher, aad, _)) => {
+                    assert_eq!(cipher.len() as u64, cipher_len);
+                    assert_eq!(aad.len() as u64, aad_len);
+                    assert_eq!(
+                        data.len(),
+                        header_len + cipher.len() + aad.len() + NONCE_SIZE
+                    );
+                }
+                Err(err) => match err {
+                    ConfidentialError::InvalidPayload(_) => {}
+                    err => panic!("unexpected error: {}", err),
+                },
+            }
+
+            // Random payloads never decrypt.
+            match decrypt(Some(data), PrivateKey::default()) {
+                Err(ConfidentialError::InvalidPayload(_))
+                | Err(ConfidentialError::PayloadOpenFailed) => {}
+                result => panic!("unexpected result: {:?}", result.map(|d| d.plaintext)),
+            }
+        }
+
+        // Lengths which overflow.
+        let mut data = vec![0u8; header_len + NONCE_SIZE];
+        data[PublicKey::len()..PublicKey::len() + CIPHER_LEN_SIZE]
+            .copy_from_slice(&u64::max_value().to_le_bytes());
+        data[PublicKey::len() + CIPHER_LEN_SIZE..header_len]
+            .copy_from_slice(&u64::max_value().to_le_bytes());
+        assert!(split_encrypted_payload(data).is_err());
+    }
+}
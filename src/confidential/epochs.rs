@@ -0,0 +1,83 @@
+//! Key manager epochs, in which contracts' ephemeral input keys rotate.
+
+use ekiden_keymanager::Epoch;
+use failure::{format_err, Fallible};
+
+/// Schedule of key manager epochs, in blocks.
+///
+/// Transactions are encrypted to the ephemeral key of the current epoch. To
+/// allow for transactions encrypted shortly before an epoch ends, the keys of
+/// the previous epoch are still accepted for a grace window at the start of
+/// each epoch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Epochs {
+    /// Number of blocks per epoch.
+    interval: u64,
+    /// Number of blocks at the start of an epoch during which the keys of the
+    /// previous epoch are still accepted.
+    grace: u64,
+}
+
+impl Epochs {
+    pub fn new(interval: u64, grace: u64) -> Fallible<Self> {
+        if interval == 0 {
+            return Err(format_err!("Epoch interval must be positive"));
+        }
+        if grace > interval {
+            return Err(format_err!(
+                "Epoch grace window cannot be longer than the epoch interval"
+            ));
+        }
+
+        Ok(Self { interval, grace })
+    }
+
+    /// Epoch of the given block.
+    pub fn epoch(&self, block_number: u64) -> Epoch {
+        block_number / self.interval
+    }
+
+    /// Epochs whose keys are accepted in the given block, most recent first.
+    pub fn accepted(&self, block_number: u64) -> Vec<Epoch> {
+        let epoch = self.epoch(block_number);
+        match epoch > 0 && block_number % self.interval < self.grace {
+            true => vec![epoch, epoch - 1],
+            false => vec![epoch],
+        }
+    }
+
+    /// Last block in which the keys of the given epoch are accepted.
+    pub fn expiry(&self, epoch: Epoch) -> u64 {
+        (epoch + 1) * self.interval + self.grace - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epochs() {
+        assert!(Epochs::new(0, 0).is_err());
+        assert!(Epochs::new(10, 11).is_err());
+
+        let epochs = Epochs::new(10, 3).unwrap();
+        assert_eq!(epochs.epoch(0), 0);
+        assert_eq!(epochs.epoch(9), 0);
+        assert_eq!(epochs.epoch(10), 1);
+
+        assert_eq!(epochs.accepted(0), vec![0]);
+        assert_eq!(epochs.accepted(9), vec![0]);
+        assert_eq!(epochs.accepted(10), vec![1, 0]);
+        assert_eq!(epochs.accepted(12), vec![1, 0]);
+        assert_eq!(epochs.accepted(13), vec![1]);
+
+        assert_eq!(epochs.expiry(0), 12);
+        assert_eq!(epochs.expiry(1), 22);
+
+        // Without a grace window, keys expire with their epoch.
+        let epochs = Epochs::new(10, 0).unwrap();
+        assert_eq!(epochs.accepted(10), vec![1]);
+        assert_eq!(epochs.expiry(0), 9);
+    }
+}
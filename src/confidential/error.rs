@@ -0,0 +1,159 @@
+//! Errors of confidential execution.
+
+use std::fmt;
+
+use ethcore::vm::Error as VmError;
+
+/// Error of confidential execution.
+///
+/// The VM carries confidential errors as `Error::Confidential` messages, so
+/// each variant's message is `PREFIX` followed by a distinct description,
+/// from which `ConfidentialError::code_of` recovers the variant's JSON-RPC
+/// error code.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfidentialError {
+    /// The context has no contract keys, e.g., because it is not activated
+    /// or the contract is not confidential.
+    MissingContractKey,
+    /// The context lacks the state needed to encrypt a message to the peer.
+    MissingSession(String),
+    /// An encrypted payload is malformed.
+    InvalidPayload(String),
+    /// An encrypted payload failed to decrypt under the contract's keys.
+    PayloadOpenFailed,
+    /// A message could not be encrypted to the peer.
+    EncryptionFailed(String),
+    /// The key manager failed to provide a contract's keys.
+    KeyManager(String),
+    /// The counter of a nonce is exhausted.
+    NonceExhausted,
+    /// A value in contract storage is corrupted.
+    CorruptedStorage(String),
+}
+
+/// Prefix of the messages of confidential errors.
+const PREFIX: &str = "confidential: ";
+
+/// Description and JSON-RPC error code of each variant, in declaration order.
+const ERRORS: &[(&str, i64)] = &[
+    ("missing contract key", -32090),
+    ("missing confidential session", -32091),
+    ("invalid encrypted payload", -32092),
+    ("failed to open encrypted payload", -32093),
+    ("failed to encrypt payload", -32094),
+    ("key manager request failed", -32095),
+    ("nonce exhausted", -32096),
+    ("corrupted confidential storage", -32097),
+];
+
+impl ConfidentialError {
+    fn index(&self) -> usize {
+        match self {
+            ConfidentialError::MissingContractKey => 0,
+            ConfidentialError::MissingSession(_) => 1,
+            ConfidentialError::InvalidPayload(_) => 2,
+            ConfidentialError::PayloadOpenFailed => 3,
+            ConfidentialError::EncryptionFailed(_) => 4,
+            ConfidentialError::KeyManager(_) => 5,
+            ConfidentialError::NonceExhausted => 6,
+            ConfidentialError::CorruptedStorage(_) => 7,
+        }
+    }
+
+    fn detail(&self) -> Option<&str> {
+        match self {
+            ConfidentialError::MissingSession(detail)
+            | ConfidentialError::InvalidPayload(detail)
+            | ConfidentialError::EncryptionFailed(detail)
+            | ConfidentialError::KeyManager(detail)
+            | ConfidentialError::CorruptedStorage(detail) => Some(detail),
+            ConfidentialError::MissingContractKey
+            | ConfidentialError::PayloadOpenFailed
+            | ConfidentialError::NonceExhausted => None,
+        }
+    }
+
+    /// JSON-RPC error code of the error.
+    pub fn code(&self) -> i64 {
+        ERRORS[self.index()].1
+    }
+
+    /// JSON-RPC error code of the confidential error with the given
+    /// message, i.e., the payload of an `Error::Confidential`, if any.
+    pub fn code_of(message: &str) -> Option<i64> {
+        if !message.starts_with(PREFIX) {
+            return None;
+        }
+        let message = &message[PREFIX.len()..];
+
+        ERRORS
+            .iter()
+            .find(|(description, _)| {
+                message.starts_with(description)
+                    && (message.len() == description.len()
+                        || message[description.len()..].starts_with(": "))
+            })
+            .map(|(_, code)| *code)
+    }
+}
+
+impl fmt::Display for ConfidentialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = ERRORS[self.index()].0;
+        match self.detail() {
+            Some(detail) => write!(f, "{}{}: {}", PREFIX, description, detail),
+            None => write!(f, "{}{}", PREFIX, description),
+        }
+    }
+}
+
+impl From<ConfidentialError> for VmError {
+    fn from(err: ConfidentialError) -> Self {
+        VmError::Confidential(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_code_of() {
+        let errors = vec![
+            ConfidentialError::MissingContractKey,
+            ConfidentialError::MissingSession("no peer public key".to_string()),
+            ConfidentialError::InvalidPayload("truncated".to_string()),
+            ConfidentialError::PayloadOpenFailed,
+            ConfidentialError::EncryptionFailed("bad key".to_string()),
+            ConfidentialError::KeyManager("unreachable".to_string()),
+            ConfidentialError::NonceExhausted,
+            ConfidentialError::CorruptedStorage("truncated".to_string()),
+        ];
+
+        // Codes are distinct, and recovered from the VM error's message.
+        let codes: HashSet<_> = errors.iter().map(ConfidentialError::code).collect();
+        assert_eq!(codes.len(), ERRORS.len());
+        for err in errors {
+            match VmError::from(err.clone()) {
+                VmError::Confidential(message) => {
+                    assert_eq!(ConfidentialError::code_of(&message), Some(err.code()))
+                }
+                _ => panic!("confidential errors must convert to Error::Confidential"),
+            }
+        }
+
+        // Descriptions only match at the start of the message.
+        assert_eq!(ConfidentialError::code_of("out of gas"), None);
+        assert_eq!(ConfidentialError::code_of("nonce exhausted"), None);
+        assert_eq!(
+            ConfidentialError::code_of("confidential: failed: nonce exhausted"),
+            None
+        );
+        assert_eq!(
+            ConfidentialError::code_of("confidential: nonce exhaustedx"),
+            None
+        );
+    }
+}
@@ -2,6 +2,16 @@
 
 mod confidential_ctx;
 mod crypto;
+mod epochs;
+mod error;
+mod storage_inspector;
+mod storage_migration;
 
 // Re-exports.
-pub use self::confidential_ctx::ConfidentialCtx;
+pub use self::{
+    confidential_ctx::ConfidentialCtx,
+    epochs::Epochs,
+    error::ConfidentialError,
+    storage_inspector::{DecryptedStorage, StorageInspector},
+    storage_migration::{split_legacy_slot, StorageMigration},
+};
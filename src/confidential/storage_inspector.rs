@@ -0,0 +1,126 @@
+//! Inspection of the encrypted storage of confidential contracts.
+
+use std::sync::{Arc, Mutex};
+
+use ethcore::vm::{AuthenticatedPayload, ConfidentialCtx as EthConfidentialCtx, Result};
+use ethereum_types::Address;
+
+use super::ConfidentialCtx;
+
+/// Storage slot of a confidential contract, as stored and as decrypted.
+#[derive(Clone, Debug)]
+pub struct DecryptedStorage {
+    /// Storage key, encrypted with the contract's state key.
+    pub encrypted_key: Vec<u8>,
+    /// Stored value, i.e., ciphertext || tag || nonce.
+    pub ciphertext: Vec<u8>,
+    /// Decrypted value.
+    pub plaintext: Vec<u8>,
+}
+
+/// Confidential context which records the storage values it decrypts, so
+/// that a storage slot read through the state can be shown both as stored
+/// and as decrypted.
+pub struct StorageInspector {
+    ctx: ConfidentialCtx,
+    decrypted: Arc<Mutex<Option<DecryptedStorage>>>,
+}
+
+impl StorageInspector {
+    /// Wrap a confidential context, returning the inspector and a handle to
+    /// the last storage value it decrypted.
+    pub fn new(ctx: ConfidentialCtx) -> (Self, Arc<Mutex<Option<DecryptedStorage>>>) {
+        let decrypted = Arc::new(Mutex::new(None));
+        (
+            Self {
+                ctx,
+                decrypted: decrypted.clone(),
+            },
+            decrypted,
+        )
+    }
+}
+
+impl EthConfidentialCtx for StorageInspector {
+    fn is_encrypting(&self) -> bool {
+        self.ctx.is_encrypting()
+    }
+
+    fn activated(&self) -> bool {
+        self.ctx.activated()
+    }
+
+    fn activate(&mut self, contract: Option<Address>) -> Result<Option<Address>> {
+        self.ctx.activate(contract)
+    }
+
+    fn deactivate(&mut self) {
+        self.ctx.deactivate()
+    }
+
+    fn encrypt_session(&mut self, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.ctx.encrypt_session(data)
+    }
+
+    fn decrypt_session(&mut self, encrypted_payload: Vec<u8>) -> Result<AuthenticatedPayload> {
+        self.ctx.decrypt_session(encrypted_payload)
+    }
+
+    fn encrypt_storage_key(&self, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.ctx.encrypt_storage_key(data)
+    }
+
+    fn encrypt_storage_value(&mut self, storage_key: Vec<u8>, data: Vec<u8>) -> Result<Vec<u8>> {
+        self.ctx.encrypt_storage_value(storage_key, data)
+    }
+
+    fn decrypt_storage_value(&self, storage_key: Vec<u8>, data: Vec<u8>) -> Result<Vec<u8>> {
+        let plaintext = self
+            .ctx
+            .decrypt_storage_value(storage_key.clone(), data.clone())?;
+        *self.decrypted.lock().unwrap() = Some(DecryptedStorage {
+            encrypted_key: storage_key,
+            ciphertext: data,
+            plaintext: plaintext.clone(),
+        });
+
+        Ok(plaintext)
+    }
+
+    fn peer(&self) -> Option<Vec<u8>> {
+        self.ctx.peer()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ekiden_keymanager::client::MockClient;
+    use ethereum_types::H256;
+
+    use super::*;
+
+    #[test]
+    fn test_records_decrypted_storage() {
+        let mut ctx = ConfidentialCtx::new(H256::default(), Arc::new(MockClient::new()));
+        ctx.activate(Some(Address::from(1))).unwrap();
+        let key = ctx.encrypt_storage_key(vec![1; 32]).unwrap();
+        let value = ctx.encrypt_storage_value(key.clone(), vec![2; 32]).unwrap();
+
+        let (mut inspector, decrypted) = StorageInspector::new(ctx);
+        assert!(decrypted.lock().unwrap().is_none());
+        assert_eq!(
+            inspector
+                .decrypt_storage_value(key.clone(), value.clone())
+                .unwrap(),
+            vec![2; 32]
+        );
+
+        let decrypted = decrypted.lock().unwrap().take().unwrap();
+        assert_eq!(decrypted.encrypted_key, key);
+        assert_eq!(decrypted.ciphertext, value);
+        assert_eq!(decrypted.plaintext, vec![2; 32]);
+
+        inspector.deactivate();
+        assert!(!inspector.activated());
+    }
+}
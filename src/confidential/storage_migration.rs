@@ -0,0 +1,140 @@
+//! Migration of confidential contract storage written by earlier versions,
+//! which encrypted storage keys with Deoxys-II under the state key itself
+//! and an all-zero nonce.
+
+use ekiden_crypto::mrae::{
+    deoxysii::{DeoxysII, KEY_SIZE, TAG_SIZE},
+    deterministic::DeterministicCipher,
+    nonce::NONCE_SIZE,
+};
+use ekiden_keymanager::ContractKey;
+use ethereum_types::Address;
+use failure::{format_err, Fallible};
+use zeroize::Zeroize;
+
+/// Length of a storage key encrypted with the legacy scheme.
+const LEGACY_KEY_SIZE: usize = 32 + TAG_SIZE;
+
+/// Split the MKVS key of a storage slot encrypted with the legacy scheme
+/// into the contract's address and the encrypted storage key.
+///
+/// The state stores each storage slot under the contract's address followed
+/// by the (encrypted) storage key. Keys of other lengths cannot be legacy
+/// storage keys.
+pub fn split_legacy_slot(key: &[u8]) -> Option<(Address, &[u8])> {
+    if key.len() != Address::len() + LEGACY_KEY_SIZE {
+        return None;
+    }
+
+    Some((
+        Address::from_slice(&key[..Address::len()]),
+        &key[Address::len()..],
+    ))
+}
+
+/// Re-encryption of the storage of a confidential contract from legacy to
+/// deterministically encrypted storage keys.
+pub struct StorageMigration {
+    /// Deoxys-II instance under the state key, as used for storage values
+    /// and legacy storage keys.
+    d2: DeoxysII,
+    /// Cipher with which storage keys are now encrypted.
+    key_cipher: DeterministicCipher,
+}
+
+impl StorageMigration {
+    pub fn new(contract_key: &ContractKey) -> Self {
+        let mut key = [0u8; KEY_SIZE];
+        key.copy_from_slice(&contract_key.state_key.as_ref()[..KEY_SIZE]);
+        let migration = Self {
+            d2: DeoxysII::new(&key),
+            key_cipher: DeterministicCipher::new(&key),
+        };
+        key.zeroize();
+        migration
+    }
+
+    /// Re-encrypt a storage key, or return `None` if it was not encrypted
+    /// with the legacy scheme under this contract's state key.
+    pub fn migrate_key(&self, legacy_key: &[u8]) -> Option<Vec<u8>> {
+        let key = self
+            .d2
+            .open(&[0u8; NONCE_SIZE], legacy_key.to_vec(), vec![])
+            .ok()?;
+        Some(self.key_cipher.seal(key, vec![]))
+    }
+
+    /// Re-encrypt a storage value, i.e., ciphertext || tag || nonce, which is
+    /// authenticated with its encrypted storage key, for the key's new
+    /// encryption. The nonce is kept.
+    pub fn migrate_value(&self, legacy_key: &[u8], key: &[u8], value: &[u8]) -> Fallible<Vec<u8>> {
+        if value.len() < TAG_SIZE + NONCE_SIZE {
+            return Err(format_err!("truncated storage value"));
+        }
+        let nonce_offset = value.len() - NONCE_SIZE;
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&value[nonce_offset..]);
+
+        let plaintext = self
+            .d2
+            .open(&nonce, value[..nonce_offset].to_vec(), legacy_key.to_vec())
+            .map_err(|err| format_err!("failed to open storage value: {}", err))?;
+        let mut migrated = self.d2.seal(&nonce, plaintext, key.to_vec());
+        migrated.extend_from_slice(&nonce);
+
+        Ok(migrated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ekiden_keymanager::{
+        client::{KeyManagerClient, MockClient},
+        ContractId,
+    };
+    use ethcore::vm::ConfidentialCtx as EthConfidentialCtx;
+    use ethereum_types::H256;
+    use hash::keccak;
+
+    use super::{super::ConfidentialCtx, *};
+
+    #[test]
+    fn test_migrate() {
+        let key_manager = Arc::new(MockClient::new());
+        let address = Address::from(1);
+        let contract_id = ContractId::from(&keccak(address.to_vec())[..]);
+        let contract_key = key_manager.get_or_create_keys(contract_id).unwrap();
+
+        // Storage slot as written by the legacy scheme.
+        let mut key = [0u8; KEY_SIZE];
+        key.copy_from_slice(&contract_key.state_key.as_ref()[..KEY_SIZE]);
+        let d2 = DeoxysII::new(&key);
+        let legacy_key = d2.seal(&[0u8; NONCE_SIZE], vec![1; 32], vec![]);
+        let nonce = [7u8; NONCE_SIZE];
+        let mut legacy_value = d2.seal(&nonce, vec![2; 32], legacy_key.clone());
+        legacy_value.extend_from_slice(&nonce);
+
+        let mut slot = address.to_vec();
+        slot.extend_from_slice(&legacy_key);
+        assert_eq!(split_legacy_slot(&slot), Some((address, &legacy_key[..])));
+        assert_eq!(split_legacy_slot(&slot[1..]), None);
+
+        // The migrated slot reads as if written by the current scheme.
+        let migration = StorageMigration::new(&contract_key);
+        let key = migration.migrate_key(&legacy_key).unwrap();
+        let value = migration
+            .migrate_value(&legacy_key, &key, &legacy_value)
+            .unwrap();
+        let mut ctx = ConfidentialCtx::new(H256::default(), key_manager.clone());
+        ctx.activate(Some(address)).unwrap();
+        assert_eq!(key, ctx.encrypt_storage_key(vec![1; 32]).unwrap());
+        assert_eq!(ctx.decrypt_storage_value(key, value).unwrap(), vec![2; 32]);
+
+        // Keys of other contracts are not migrated.
+        let other_id = ContractId::from(&keccak(Address::from(2).to_vec())[..]);
+        let other = StorageMigration::new(&key_manager.get_or_create_keys(other_id).unwrap());
+        assert_eq!(other.migrate_key(&legacy_key), None);
+    }
+}
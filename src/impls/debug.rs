@@ -0,0 +1,279 @@
+//! Debug RPC implementation.
+use std::sync::Arc;
+
+use ethcore::{
+    executive::Executed,
+    trace::{
+        trace::{Action, Res},
+        FlatTrace,
+    },
+    transaction::{Action as TransactionAction, SignedTransaction},
+    vm::{CallType, Error as VmError},
+};
+use jsonrpc_core::{
+    futures::{future, Future},
+    BoxFuture,
+};
+use jsonrpc_macros::Trailing;
+use parity_rpc::v1::{
+    helpers::{errors, fake_sign},
+    metadata::Metadata,
+    types::{BlockNumber, CallRequest, TraceResults, H256 as RpcH256},
+};
+
+use crate::{
+    blockchain::Blockchain,
+    traits::{
+        debug::{RpcCallFrame, RpcDebugTrace},
+        Debug,
+    },
+    util::{decode_revert_reason, jsonrpc_error},
+};
+
+/// Debug rpc implementation.
+pub struct DebugClient {
+    blockchain: Arc<Blockchain>,
+}
+
+impl DebugClient {
+    /// Creates new DebugClient.
+    pub fn new(blockchain: Arc<Blockchain>) -> Self {
+        DebugClient { blockchain }
+    }
+}
+
+impl Debug for DebugClient {
+    type Metadata = Metadata;
+
+    fn trace_transaction(&self, hash: RpcH256) -> BoxFuture<Option<RpcDebugTrace>> {
+        Box::new(
+            self.blockchain
+                .trace_transaction(hash.into())
+                .map_err(jsonrpc_error)
+                .map(|traced| traced.map(|(txn, executed)| debug_trace(&txn, executed))),
+        )
+    }
+
+    fn trace_call(
+        &self,
+        meta: Metadata,
+        request: CallRequest,
+        num: Trailing<BlockNumber>,
+    ) -> BoxFuture<RpcDebugTrace> {
+        let num = num.unwrap_or_default();
+
+        let signed = match fake_sign::sign_call(request.into(), meta.is_dapp()) {
+            Ok(signed) => signed,
+            Err(err) => return Box::new(future::err(err)),
+        };
+
+        Box::new(
+            self.blockchain
+                .trace_call(signed.clone(), num)
+                .map_err(errors::call)
+                .map(move |executed| debug_trace(&signed, executed)),
+        )
+    }
+}
+
+/// Build the debug trace of an executed transaction.
+fn debug_trace(txn: &SignedTransaction, executed: Executed) -> RpcDebugTrace {
+    let call_trace = call_trace(txn, &executed);
+    let vm_trace = serde_json::to_value(TraceResults::from(executed).vm_trace)
+        .expect("VM trace must serialize");
+
+    RpcDebugTrace {
+        call_trace,
+        vm_trace,
+    }
+}
+
+/// Build the call tree of an executed transaction from its flat traces.
+fn call_trace(txn: &SignedTransaction, executed: &Executed) -> RpcCallFrame {
+    let mut traces = executed.trace.iter();
+    let mut root = match traces.next() {
+        Some(trace) => call_frame(trace),
+        // Execution did not start, e.g. due to insufficient balance.
+        None => RpcCallFrame {
+            call_type: match txn.action {
+                TransactionAction::Create => "CREATE",
+                TransactionAction::Call(_) => "CALL",
+            }
+            .to_string(),
+            from: txn.sender().into(),
+            to: match txn.action {
+                TransactionAction::Create => None,
+                TransactionAction::Call(to) => Some(to.into()),
+            },
+            value: txn.value.into(),
+            gas: txn.gas.into(),
+            gas_used: None,
+            input: txn.data.clone().into(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            calls: vec![],
+        },
+    };
+    for trace in traces {
+        if !insert_call_frame(&mut root, &trace.trace_address, call_frame(trace)) {
+            warn!(
+                "Skipping call frame without a parent at trace address {:?}",
+                trace.trace_address
+            );
+        }
+    }
+
+    // The top-level call accounts for the whole transaction.
+    root.gas = txn.gas.into();
+    root.gas_used = Some(executed.gas_used.into());
+    root.output = Some(executed.output.clone().into());
+    if let Some(ref exception) = executed.exception {
+        root.error = Some(exception.to_string());
+        if let VmError::Reverted = exception {
+            root.revert_reason = decode_revert_reason(&executed.output);
+        }
+    }
+
+    root
+}
+
+/// Insert a call frame into the call tree, at the given trace address.
+///
+/// Flat traces are ordered depth-first, so the parent frame and all preceding
+/// siblings are already in the tree. Returns false, leaving the tree
+/// unchanged, if the address has no parent frame in the tree.
+fn insert_call_frame(parent: &mut RpcCallFrame, address: &[usize], frame: RpcCallFrame) -> bool {
+    match address.len() {
+        // Only the top-level call has an empty trace address.
+        0 => false,
+        1 => {
+            parent.calls.push(frame);
+            true
+        }
+        _ => match parent.calls.get_mut(address[0]) {
+            Some(child) => insert_call_frame(child, &address[1..], frame),
+            None => false,
+        },
+    }
+}
+
+/// Convert a flat trace into a call frame, without its calls.
+fn call_frame(trace: &FlatTrace) -> RpcCallFrame {
+    let mut frame = match trace.action {
+        Action::Call(ref call) => RpcCallFrame {
+            call_type: match call.call_type {
+                CallType::None | CallType::Call => "CALL",
+                CallType::CallCode => "CALLCODE",
+                CallType::DelegateCall => "DELEGATECALL",
+                CallType::StaticCall => "STATICCALL",
+            }
+            .to_string(),
+            from: call.from.into(),
+            to: Some(call.to.into()),
+            value: call.value.into(),
+            gas: call.gas.into(),
+            gas_used: None,
+            input: call.input.clone().into(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            calls: vec![],
+        },
+        Action::Create(ref create) => RpcCallFrame {
+            call_type: "CREATE".to_string(),
+            from: create.from.into(),
+            to: None,
+            value: create.value.into(),
+            gas: create.gas.into(),
+            gas_used: None,
+            input: create.init.clone().into(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            calls: vec![],
+        },
+        Action::Suicide(ref suicide) => RpcCallFrame {
+            call_type: "SELFDESTRUCT".to_string(),
+            from: suicide.address.into(),
+            to: Some(suicide.refund_address.into()),
+            value: suicide.balance.into(),
+            gas: Default::default(),
+            gas_used: None,
+            input: Default::default(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            calls: vec![],
+        },
+        Action::Reward(ref reward) => RpcCallFrame {
+            call_type: "REWARD".to_string(),
+            from: Default::default(),
+            to: Some(reward.author.into()),
+            value: reward.value.into(),
+            gas: Default::default(),
+            gas_used: None,
+            input: Default::default(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            calls: vec![],
+        },
+    };
+
+    match trace.result {
+        Res::Call(ref result) => {
+            frame.gas_used = Some(result.gas_used.into());
+            frame.output = Some(result.output.clone().into());
+        }
+        Res::Create(ref result) => {
+            frame.gas_used = Some(result.gas_used.into());
+            frame.output = Some(result.code.clone().into());
+            frame.to = Some(result.address.into());
+        }
+        Res::FailedCall(ref err) | Res::FailedCreate(ref err) => {
+            frame.error = Some(err.to_string());
+        }
+        Res::None => {}
+    }
+
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(call_type: &str) -> RpcCallFrame {
+        RpcCallFrame {
+            call_type: call_type.to_string(),
+            from: Default::default(),
+            to: None,
+            value: Default::default(),
+            gas: Default::default(),
+            gas_used: None,
+            input: Default::default(),
+            output: None,
+            error: None,
+            revert_reason: None,
+            calls: vec![],
+        }
+    }
+
+    #[test]
+    fn test_insert_call_frame() {
+        let mut root = frame("CALL");
+        assert!(insert_call_frame(&mut root, &[0], frame("CREATE")));
+        assert!(insert_call_frame(&mut root, &[0, 0], frame("STATICCALL")));
+
+        // Frames without a parent in the tree are rejected.
+        assert!(!insert_call_frame(&mut root, &[], frame("CALL")));
+        assert!(!insert_call_frame(&mut root, &[1, 0], frame("CALL")));
+        assert!(!insert_call_frame(&mut root, &[0, 1, 0], frame("CALL")));
+
+        assert_eq!(root.calls.len(), 1);
+        assert_eq!(root.calls[0].call_type, "CREATE");
+        assert_eq!(root.calls[0].calls.len(), 1);
+        assert_eq!(root.calls[0].calls[0].call_type, "STATICCALL");
+    }
+}
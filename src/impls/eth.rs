@@ -18,12 +18,12 @@
 
 use std::sync::Arc;
 
-use ethcore::{filter::Filter as EthcoreFilter, ids::BlockId};
+use ethcore::{filter::Filter as EthcoreFilter, ids::BlockId, vm::Error as VmError};
 use ethereum_types::{Address, H256, U256};
 use failure::Error;
 use jsonrpc_core::{
     futures::{future, Future},
-    BoxFuture, Result,
+    BoxFuture, Params, Result, Value,
 };
 use jsonrpc_macros::Trailing;
 use parity_rpc::v1::{
@@ -38,8 +38,10 @@ use parity_rpc::v1::{
 };
 
 use crate::{
-    blockchain::Blockchain,
-    util::{block_number_to_id, execution_error, jsonrpc_error},
+    blockchain::{Blockchain, EstimateGasError, StateOverride},
+    util::{
+        block_number_to_id, call_error, execution_error, jsonrpc_error, revert_error, vm_error,
+    },
 };
 
 // short for "try_boxfuture"
@@ -81,6 +83,50 @@ impl EthClient {
     pub fn new(blockchain: Arc<Blockchain>) -> Self {
         EthClient { blockchain }
     }
+
+    /// Executes a call against a given block, with optional state overrides.
+    pub fn call_with_overrides(
+        &self,
+        meta: Metadata,
+        request: CallRequest,
+        num: BlockNumber,
+        overrides: Option<StateOverride>,
+    ) -> BoxFuture<Bytes> {
+        let signed = try_bf!(fake_sign::sign_call(request.into(), meta.is_dapp()));
+
+        Box::new(
+            self.blockchain
+                .simulate_transaction(signed, num, overrides)
+                .map_err(call_error)
+                .and_then(|executed| match executed.exception {
+                    Some(ref exception) => Err(vm_error(exception, &executed.output)),
+                    None => Ok(executed),
+                })
+                .map(|executed| executed.output.into()),
+        )
+    }
+
+    /// Handles `eth_call` with a Geth-style state override set as an
+    /// optional third parameter.
+    pub fn call_with_params(&self, params: Params, meta: Metadata) -> BoxFuture<Value> {
+        let mut values: Vec<Value> = try_bf!(params.parse());
+        if values.is_empty() || values.len() > 3 {
+            return Box::new(future::err(errors::invalid_params(
+                "eth_call",
+                "Expected a call request, and an optional block number and state override set.",
+            )));
+        }
+        values.resize(3, Value::Null);
+
+        let (request, num, overrides): (CallRequest, Option<BlockNumber>, Option<StateOverride>) =
+            try_bf!(serde_json::from_value(Value::Array(values))
+                .map_err(|err| errors::invalid_params("eth_call", err)));
+
+        Box::new(
+            self.call_with_overrides(meta, request, num.unwrap_or_default(), overrides)
+                .map(|output| serde_json::to_value(output).expect("bytes must serialize")),
+        )
+    }
 }
 
 impl Eth for EthClient {
@@ -125,7 +171,7 @@ impl Eth for EthClient {
         let address = address.into();
         let num = num.unwrap_or_default();
 
-        let state = match self.blockchain.state(block_number_to_id(num)) {
+        let state = match self.blockchain.state_at(num) {
             Ok(state) => state,
             Err(err) => return Box::new(future::err(jsonrpc_error(err))),
         };
@@ -148,7 +194,7 @@ impl Eth for EthClient {
         let pos: U256 = RpcU256::into(pos);
         let num = num.unwrap_or_default();
 
-        let state = match self.blockchain.state(block_number_to_id(num)) {
+        let state = match self.blockchain.state_at(num) {
             Ok(state) => state,
             Err(err) => return Box::new(future::err(jsonrpc_error(err))),
         };
@@ -169,7 +215,7 @@ impl Eth for EthClient {
         let address: Address = RpcH160::into(address);
         let num = num.unwrap_or_default();
 
-        let state = match self.blockchain.state(block_number_to_id(num)) {
+        let state = match self.blockchain.state_at(num) {
             Ok(state) => state,
             Err(err) => return Box::new(future::err(jsonrpc_error(err))),
         };
@@ -192,9 +238,10 @@ impl Eth for EthClient {
     }
 
     fn block_transaction_count_by_number(&self, num: BlockNumber) -> BoxFuture<Option<RpcU256>> {
-        // We don't track pending transactions.
+        // The pending block contains the transactions in the pending pool.
         if let BlockNumber::Pending = num {
-            return Box::new(future::ok(Some(0.into())));
+            let count = self.blockchain.pending_transactions().len();
+            return Box::new(future::ok(Some(count.into())));
         }
 
         Box::new(
@@ -219,7 +266,7 @@ impl Eth for EthClient {
         let address: Address = RpcH160::into(address);
         let num = num.unwrap_or_default();
 
-        let state = match self.blockchain.state(block_number_to_id(num)) {
+        let state = match self.blockchain.state_at(num) {
             Ok(state) => state,
             Err(err) => return Box::new(future::err(jsonrpc_error(err))),
         };
@@ -266,13 +313,17 @@ impl Eth for EthClient {
 
     fn transaction_by_hash(&self, hash: RpcH256) -> BoxFuture<Option<RpcTransaction>> {
         let hash = hash.into();
+        let blockchain = self.blockchain.clone();
 
         Box::new(
             self.blockchain
                 .get_txn_by_hash(hash)
-                .and_then(move |txn| {
-                    txn.map(|txn| Ok(RpcTransaction::from_localized(txn)))
-                        .transpose()
+                .map(move |txn| match txn {
+                    Some(txn) => Some(RpcTransaction::from_localized(txn)),
+                    // Submitted transactions which are not yet mined have no block.
+                    None => blockchain
+                        .get_pending_txn_by_hash(hash)
+                        .map(RpcTransaction::from_signed),
                 })
                 .map_err(jsonrpc_error),
         )
@@ -394,20 +445,7 @@ impl Eth for EthClient {
         request: CallRequest,
         num: Trailing<BlockNumber>,
     ) -> BoxFuture<Bytes> {
-        let num = num.unwrap_or_default();
-
-        let signed = try_bf!(fake_sign::sign_call(request.into(), meta.is_dapp()));
-
-        Box::new(
-            self.blockchain
-                .simulate_transaction(signed, block_number_to_id(num))
-                .map_err(errors::call)
-                .and_then(|executed| match executed.exception {
-                    Some(ref exception) => Err(errors::vm(exception, &executed.output)),
-                    None => Ok(executed),
-                })
-                .map(|executed| executed.output.into()),
-        )
+        self.call_with_overrides(meta, request, num.unwrap_or_default(), None)
     }
 
     fn estimate_gas(
@@ -422,8 +460,18 @@ impl Eth for EthClient {
 
         Box::new(
             self.blockchain
-                .estimate_gas(signed, block_number_to_id(num))
-                .map_err(execution_error)
+                .estimate_gas(signed, num)
+                .map_err(|err| match err {
+                    EstimateGasError::Call(err) => execution_error(err),
+                    EstimateGasError::Execution {
+                        error: VmError::Reverted,
+                        output,
+                    } => revert_error(&output),
+                    EstimateGasError::Execution { error, output } => vm_error(&error, &output),
+                    EstimateGasError::GasAllowanceExceeded(allowance) => {
+                        execution_error(format!("gas required exceeds allowance ({})", allowance))
+                    }
+                })
                 .map(Into::into),
         )
     }
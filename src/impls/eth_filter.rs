@@ -16,7 +16,7 @@
 
 //! Eth Filter RPC implementation
 
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use ethcore::{filter::Filter as EthcoreFilter, ids::BlockId};
 use failure::format_err;
@@ -86,9 +86,9 @@ impl EthFilter for EthFilterClient {
     }
 
     fn new_pending_transaction_filter(&self) -> Result<RpcU256> {
-        // We don't have pending transactions, so this is a no-op filter.
         let mut polls = self.polls.lock();
-        let id = polls.create_poll(PollFilter::PendingTransaction(vec![]));
+        let pending_transactions = self.blockchain.pending_transaction_hashes();
+        let id = polls.create_poll(PollFilter::PendingTransaction(pending_transactions));
         Ok(id.into())
     }
 
@@ -122,9 +122,25 @@ impl EthFilter for EthFilterClient {
                             *number = blk.number_u64();
                             updates
                         }
-                        Some(PollFilter::PendingTransaction(_)) => {
-                            // We don't have pending transactions, so this is a no-op filter.
-                            Box::new(future::ok(FilterChanges::Hashes(vec![])))
+                        Some(PollFilter::PendingTransaction(ref mut previous_hashes)) => {
+                            // Get the difference between current and previous hashes.
+                            let current_hashes = blockchain.pending_transaction_hashes();
+                            let new_hashes = {
+                                let previous_hashes_set =
+                                    previous_hashes.iter().collect::<HashSet<_>>();
+
+                                current_hashes
+                                    .iter()
+                                    .filter(|hash| !previous_hashes_set.contains(hash))
+                                    .cloned()
+                                    .map(Into::into)
+                                    .collect()
+                            };
+
+                            // Save the current hashes.
+                            *previous_hashes = current_hashes;
+
+                            Box::new(future::ok(FilterChanges::Hashes(new_hashes)))
                         }
                         Some(PollFilter::Logs(ref mut block_number, _, ref filter)) => {
                             // Build appropriate filter.
@@ -24,6 +24,7 @@ use ethcore::{
     filter::{Filter as EthFilter, TxEntry as EthTxEntry, TxFilter as EthTxFilter},
     ids::BlockId,
 };
+use ethereum_types::H256;
 use failure::format_err;
 use futures::{prelude::*, stream};
 use jsonrpc_core::Result;
@@ -52,6 +53,7 @@ pub struct EthPubSubClient {
     heads_subscribers: Arc<RwLock<Subscribers<PubSubClient>>>,
     logs_subscribers: Arc<RwLock<Subscribers<(PubSubClient, EthFilter)>>>,
     tx_subscribers: Arc<RwLock<Subscribers<(PubSubClient, EthTxFilter)>>>,
+    pending_subscribers: Arc<RwLock<Subscribers<PubSubClient>>>,
 }
 
 impl EthPubSubClient {
@@ -60,6 +62,7 @@ impl EthPubSubClient {
         let heads_subscribers = Arc::new(RwLock::new(Subscribers::default()));
         let logs_subscribers = Arc::new(RwLock::new(Subscribers::default()));
         let tx_subscribers = Arc::new(RwLock::new(Subscribers::default()));
+        let pending_subscribers = Arc::new(RwLock::new(Subscribers::default()));
 
         EthPubSubClient {
             handler: Arc::new(ChainNotificationHandler {
@@ -67,10 +70,12 @@ impl EthPubSubClient {
                 heads_subscribers: heads_subscribers.clone(),
                 logs_subscribers: logs_subscribers.clone(),
                 tx_subscribers: tx_subscribers.clone(),
+                pending_subscribers: pending_subscribers.clone(),
             }),
             heads_subscribers,
             logs_subscribers,
             tx_subscribers,
+            pending_subscribers,
         }
     }
 
@@ -86,6 +91,7 @@ pub struct ChainNotificationHandler {
     heads_subscribers: Arc<RwLock<Subscribers<PubSubClient>>>,
     logs_subscribers: Arc<RwLock<Subscribers<(PubSubClient, EthFilter)>>>,
     tx_subscribers: Arc<RwLock<Subscribers<(PubSubClient, EthTxFilter)>>>,
+    pending_subscribers: Arc<RwLock<Subscribers<PubSubClient>>>,
 }
 
 impl ChainNotificationHandler {
@@ -177,6 +183,14 @@ impl Listener for ChainNotificationHandler {
             );
         }
     }
+
+    fn notify_pending_transactions(&self, hashes: &[H256]) {
+        for subscriber in self.pending_subscribers.read().values() {
+            for hash in hashes {
+                Self::notify(subscriber, pubsub::Result::TransactionHash((*hash).into()));
+            }
+        }
+    }
 }
 
 impl EthPubSub for EthPubSubClient {
@@ -208,8 +222,13 @@ impl EthPubSub for EthPubSubClient {
                 self.tx_subscribers.write().push(subscriber, filter.into());
                 return;
             }
-            // we don't track pending transactions currently
-            (pubsub::Kind::NewPendingTransactions, _) => errors::unimplemented(None),
+            (pubsub::Kind::NewPendingTransactions, None) => {
+                self.pending_subscribers.write().push(subscriber);
+                return;
+            }
+            (pubsub::Kind::NewPendingTransactions, _) => {
+                errors::invalid_params("newPendingTransactions", "Expected no parameters.")
+            }
             _ => errors::unimplemented(None),
         };
 
@@ -220,7 +239,8 @@ impl EthPubSub for EthPubSubClient {
         let res = self.heads_subscribers.write().remove(&id).is_some();
         let res2 = self.logs_subscribers.write().remove(&id).is_some();
         let res3 = self.tx_subscribers.write().remove(&id).is_some();
+        let res4 = self.pending_subscribers.write().remove(&id).is_some();
 
-        Ok(res || res2 || res3)
+        Ok(res || res2 || res3 || res4)
     }
 }
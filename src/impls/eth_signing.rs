@@ -1,4 +1,10 @@
-use jsonrpc_core::{futures::future, BoxFuture};
+use std::sync::Arc;
+
+use ethcore::transaction::{Action, Transaction};
+use jsonrpc_core::{
+    futures::{future, Future},
+    BoxFuture,
+};
 use parity_rpc::v1::{
     helpers::errors,
     metadata::Metadata,
@@ -9,11 +15,18 @@ use parity_rpc::v1::{
     },
 };
 
-pub struct EthSigningClient {}
+use crate::{
+    blockchain::Blockchain,
+    util::{execution_error, jsonrpc_error},
+};
+
+pub struct EthSigningClient {
+    blockchain: Arc<Blockchain>,
+}
 
 impl EthSigningClient {
-    pub fn new() -> EthSigningClient {
-        EthSigningClient {}
+    pub fn new(blockchain: Arc<Blockchain>) -> EthSigningClient {
+        EthSigningClient { blockchain }
     }
 }
 
@@ -25,9 +38,45 @@ impl EthSigning for EthSigningClient {
             Make sure that the wallet is setup correctly in the client in case transaction signing is expected to happen transparently".to_string(), None)))
     }
 
-    fn send_transaction(&self, _: Metadata, _: TransactionRequest) -> BoxFuture<RpcH256> {
-        Box::new(future::failed(errors::unsupported("eth_sendTransaction is not implemented because the gateway cannot sign transactions. \
-            Make sure that the wallet is setup correctly in the client in case transaction signing is expected to happen transparently".to_string(), None)))
+    fn send_transaction(&self, _: Metadata, request: TransactionRequest) -> BoxFuture<RpcH256> {
+        // Only transactions from impersonated accounts can be sent unsigned.
+        let sender = match request.from {
+            Some(from) if self.blockchain.is_impersonated(&from.into()) => from.into(),
+            _ => return Box::new(future::failed(errors::unsupported("eth_sendTransaction is not implemented because the gateway cannot sign transactions. \
+                Make sure that the wallet is setup correctly in the client in case transaction signing is expected to happen transparently".to_string(), None))),
+        };
+
+        let nonce = match request.nonce {
+            Some(nonce) => nonce.into(),
+            None => match self.blockchain.next_nonce(&sender) {
+                Ok(nonce) => nonce,
+                Err(err) => return Box::new(future::failed(jsonrpc_error(err))),
+            },
+        };
+        let txn = Transaction {
+            nonce,
+            gas_price: request
+                .gas_price
+                .map(Into::into)
+                .unwrap_or_else(|| self.blockchain.gas_price()),
+            // Default to a fifth of the block gas limit, like parity.
+            gas: request
+                .gas
+                .map(Into::into)
+                .unwrap_or_else(|| self.blockchain.block_gas_limit() / 5),
+            action: request
+                .to
+                .map_or(Action::Create, |to| Action::Call(to.into())),
+            value: request.value.map(Into::into).unwrap_or_default(),
+            data: request.data.map(Into::into).unwrap_or_default(),
+        };
+
+        Box::new(
+            self.blockchain
+                .send_impersonated_transaction(txn.fake_sign(sender))
+                .map(|(hash, _result)| hash.into())
+                .map_err(execution_error),
+        )
     }
 
     fn sign_transaction(
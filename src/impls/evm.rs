@@ -0,0 +1,98 @@
+//! EVM development RPC implementation.
+use std::sync::Arc;
+
+use jsonrpc_core::Result;
+use jsonrpc_macros::Trailing;
+use parity_rpc::v1::types::{Bytes, H160 as RpcH160, H256 as RpcH256, U256 as RpcU256};
+
+use crate::{blockchain::Blockchain, traits::Evm, util::jsonrpc_error};
+
+/// EVM development rpc implementation.
+pub struct EvmClient {
+    blockchain: Arc<Blockchain>,
+}
+
+impl EvmClient {
+    /// Creates new EvmClient.
+    pub fn new(blockchain: Arc<Blockchain>) -> Self {
+        EvmClient { blockchain }
+    }
+}
+
+impl Evm for EvmClient {
+    fn snapshot(&self) -> Result<RpcU256> {
+        Ok(self.blockchain.snapshot().map_err(jsonrpc_error)?.into())
+    }
+
+    fn revert(&self, id: RpcU256) -> Result<bool> {
+        let id: u64 = id.into();
+        self.blockchain.revert(id).map_err(jsonrpc_error)
+    }
+
+    fn mine(&self, timestamp: Trailing<u64>) -> Result<RpcU256> {
+        self.blockchain
+            .mine(timestamp.into())
+            .map_err(jsonrpc_error)?;
+
+        Ok(RpcU256::from(0))
+    }
+
+    fn increase_time(&self, seconds: u64) -> Result<i64> {
+        Ok(self.blockchain.increase_time(seconds))
+    }
+
+    fn set_next_block_timestamp(&self, timestamp: u64) -> Result<bool> {
+        self.blockchain
+            .set_next_block_timestamp(timestamp)
+            .map_err(jsonrpc_error)?;
+
+        Ok(true)
+    }
+
+    fn impersonate_account(&self, address: RpcH160) -> Result<bool> {
+        self.blockchain.impersonate_account(address.into());
+
+        Ok(true)
+    }
+
+    fn stop_impersonating_account(&self, address: RpcH160) -> Result<bool> {
+        Ok(self.blockchain.stop_impersonating_account(&address.into()))
+    }
+
+    fn set_account_balance(&self, address: RpcH160, balance: RpcU256) -> Result<bool> {
+        self.blockchain
+            .set_balance(address.into(), balance.into())
+            .map_err(jsonrpc_error)?;
+
+        Ok(true)
+    }
+
+    fn set_account_nonce(&self, address: RpcH160, nonce: RpcU256) -> Result<bool> {
+        self.blockchain
+            .set_nonce(address.into(), nonce.into())
+            .map_err(jsonrpc_error)?;
+
+        Ok(true)
+    }
+
+    fn set_account_code(&self, address: RpcH160, code: Bytes) -> Result<bool> {
+        self.blockchain
+            .set_code(address.into(), code.into())
+            .map_err(jsonrpc_error)?;
+
+        Ok(true)
+    }
+
+    fn set_account_storage_at(
+        &self,
+        address: RpcH160,
+        key: RpcH256,
+        value: RpcH256,
+    ) -> Result<bool> {
+        self.blockchain
+            .set_storage(address.into(), key.into(), value.into())
+            .map_err(jsonrpc_error)?;
+
+        Ok(true)
+    }
+}
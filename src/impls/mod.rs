@@ -19,18 +19,23 @@
 //! This doesn't re-implement all of the RPC APIs, just those which aren't
 //! significantly generic to be reused.
 
+pub mod debug;
 pub mod eth;
 pub mod eth_filter;
 #[cfg(feature = "pubsub")]
 pub mod eth_pubsub;
 pub mod eth_signing;
+pub mod evm;
 pub mod net;
 pub mod oasis;
+pub mod oasis_dev;
+pub mod traces;
 pub mod web3;
 
 #[cfg(feature = "pubsub")]
 pub use self::eth_pubsub::EthPubSubClient;
 pub use self::{
-    eth::EthClient, eth_filter::EthFilterClient, eth_signing::EthSigningClient, net::NetClient,
-    oasis::OasisClient, web3::Web3Client,
+    debug::DebugClient, eth::EthClient, eth_filter::EthFilterClient, eth_signing::EthSigningClient,
+    evm::EvmClient, net::NetClient, oasis::OasisClient, oasis_dev::OasisDevClient,
+    traces::TracesClient, web3::Web3Client,
 };
@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use ekiden_keymanager::{client::MockClient, ContractId};
+use ekiden_keymanager::{client::KeyManagerClient, ContractId};
 use ethereum_types::Address;
 use futures::prelude::*;
 use hash::keccak;
@@ -14,18 +14,18 @@ use parity_rpc::v1::{
 use crate::{
     blockchain::Blockchain,
     traits::oasis::{Oasis, RpcExecutionPayload, RpcPublicKeyPayload},
-    util::{block_number_to_id, execution_error, jsonrpc_error},
+    util::{decode_revert_reason, execution_error, jsonrpc_error},
 };
 
 /// Eth rpc implementation
 pub struct OasisClient {
     blockchain: Arc<Blockchain>,
-    km_client: Arc<MockClient>,
+    km_client: Arc<dyn KeyManagerClient>,
 }
 
 impl OasisClient {
     /// Creates new OasisClient.
-    pub fn new(blockchain: Arc<Blockchain>, km_client: Arc<MockClient>) -> Self {
+    pub fn new(blockchain: Arc<Blockchain>, km_client: Arc<dyn KeyManagerClient>) -> Self {
         OasisClient {
             blockchain,
             km_client,
@@ -39,20 +39,48 @@ impl Oasis for OasisClient {
     fn public_key(&self, contract: Address) -> BoxFuture<Option<RpcPublicKeyPayload>> {
         let contract_id = ContractId::from(&keccak(contract.to_vec())[..]);
 
-        Box::new(future::ok(self.km_client.get_public_key(contract_id).map(
-            |pk_payload| RpcPublicKeyPayload {
-                public_key: Bytes::from(pk_payload.key.as_ref().to_vec()),
-                checksum: Bytes::from(pk_payload.checksum),
-                signature: Bytes::from(pk_payload.signature.as_ref().to_vec()),
-            },
-        )))
+        // Transactions sent now are included in the next block at the
+        // earliest, so hand out the key of that block's epoch.
+        let epochs = self.blockchain.epochs();
+        let pk_payload = match epochs {
+            Some(epochs) => {
+                let epoch = epochs.epoch(self.blockchain.best_block_number() + 1);
+                self.km_client.get_ephemeral_public_key(contract_id, epoch)
+            }
+            None => self.km_client.get_public_key(contract_id),
+        };
+
+        Box::new(future::done(
+            pk_payload
+                .map_err(|err| jsonrpc_error(err.into()))
+                .map(|pk_payload| {
+                    pk_payload.map(|pk_payload| RpcPublicKeyPayload {
+                        public_key: Bytes::from(pk_payload.key.as_ref().to_vec()),
+                        checksum: Bytes::from(pk_payload.checksum),
+                        signature: Bytes::from(pk_payload.signature.as_ref().to_vec()),
+                        epoch: pk_payload.epoch,
+                        expiry: pk_payload
+                            .epoch
+                            .and_then(|epoch| epochs.map(|epochs| epochs.expiry(epoch))),
+                    })
+                }),
+        ))
+    }
+
+    fn key_manager_public_key(&self) -> BoxFuture<Bytes> {
+        Box::new(future::done(
+            self.km_client
+                .get_signer_public_key()
+                .map_err(|err| jsonrpc_error(err.into()))
+                .map(|key| Bytes::from(key.as_ref().to_vec())),
+        ))
     }
 
     fn get_expiry(&self, address: RpcH160, num: Trailing<BlockNumber>) -> BoxFuture<u64> {
         let address: Address = RpcH160::into(address);
         let num = num.unwrap_or_default();
 
-        let state = match self.blockchain.state(block_number_to_id(num)) {
+        let state = match self.blockchain.state_at(num) {
             Ok(state) => state,
             Err(err) => return Box::new(future::err(jsonrpc_error(err))),
         };
@@ -69,11 +97,17 @@ impl Oasis for OasisClient {
         Box::new(
             self.blockchain
                 .send_raw_transaction(raw.into())
+                // Wait for the transaction to be mined.
+                .and_then(|(hash, result)| result.map(move |result| (hash, result)))
                 .map_err(execution_error)
                 .then(move |maybe_result| {
                     maybe_result.map(|(hash, result)| RpcExecutionPayload {
                         transaction_hash: hash.into(),
                         status_code: (result.status_code as u64).into(),
+                        revert_reason: match result.status_code {
+                            0 => decode_revert_reason(&result.output),
+                            _ => None,
+                        },
                         output: result.output.into(),
                     })
                 }),
@@ -0,0 +1,98 @@
+//! Oasis development RPC implementation.
+use std::sync::Arc;
+
+use ekiden_keymanager::{
+    client::KeyManagerClient, ContractId, ContractKey, PrivateKey, PublicKey, StateKey,
+};
+use ethereum_types::Address;
+use hash::keccak;
+use jsonrpc_core::Result;
+use jsonrpc_macros::Trailing;
+use parity_rpc::v1::{
+    helpers::errors,
+    types::{BlockNumber, Bytes, H256 as RpcH256},
+};
+
+use crate::{
+    blockchain::Blockchain,
+    traits::oasis_dev::{OasisDev, RpcContractKey, RpcDecryptedStorage},
+    util::{self, jsonrpc_error},
+};
+
+/// Oasis development rpc implementation.
+pub struct OasisDevClient {
+    blockchain: Arc<Blockchain>,
+    km_client: Arc<dyn KeyManagerClient>,
+}
+
+impl OasisDevClient {
+    /// Creates new OasisDevClient.
+    pub fn new(blockchain: Arc<Blockchain>, km_client: Arc<dyn KeyManagerClient>) -> Self {
+        OasisDevClient {
+            blockchain,
+            km_client,
+        }
+    }
+}
+
+/// Converts a byte string to a 32-byte key.
+fn key_from_bytes(name: &str, bytes: Bytes) -> Result<[u8; 32]> {
+    if bytes.0.len() != 32 {
+        return Err(errors::invalid_params(name, "Expected 32 bytes."));
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&bytes.0);
+    Ok(key)
+}
+
+impl OasisDev for OasisDevClient {
+    fn export_contract_key(&self, contract: Address) -> Result<RpcContractKey> {
+        let contract_id = ContractId::from(&keccak(contract.to_vec())[..]);
+        let key = self
+            .km_client
+            .get_or_create_keys(contract_id)
+            .map_err(|err| jsonrpc_error(err.into()))?;
+
+        Ok(RpcContractKey {
+            public_key: Bytes::from(key.input_keypair.get_pk().as_ref().to_vec()),
+            private_key: Bytes::from(key.input_keypair.get_sk().as_ref().to_vec()),
+            state_key: Bytes::from(key.state_key.as_ref().to_vec()),
+            checksum: Bytes::from(key.checksum),
+        })
+    }
+
+    fn import_contract_key(&self, contract: Address, key: RpcContractKey) -> Result<bool> {
+        let contract_id = ContractId::from(&keccak(contract.to_vec())[..]);
+        let key = ContractKey::new(
+            PublicKey(key_from_bytes("public_key", key.public_key)?),
+            PrivateKey(key_from_bytes("private_key", key.private_key)?),
+            StateKey(key_from_bytes("state_key", key.state_key)?),
+            key.checksum.0,
+        );
+
+        self.km_client
+            .import_keys(contract_id, key)
+            .map_err(|err| jsonrpc_error(err.into()))?;
+
+        Ok(true)
+    }
+
+    fn decrypted_storage(
+        &self,
+        contract: Address,
+        key: RpcH256,
+        num: Trailing<BlockNumber>,
+    ) -> Result<Option<RpcDecryptedStorage>> {
+        let id = util::block_number_to_id(num.unwrap_or_default());
+        let decrypted = self
+            .blockchain
+            .decrypted_storage(contract, key.into(), id)
+            .map_err(jsonrpc_error)?;
+
+        Ok(decrypted.map(|decrypted| RpcDecryptedStorage {
+            encrypted_key: Bytes::from(decrypted.encrypted_key),
+            ciphertext: Bytes::from(decrypted.ciphertext),
+            plaintext: Bytes::from(decrypted.plaintext),
+        }))
+    }
+}
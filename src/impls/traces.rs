@@ -0,0 +1,105 @@
+//! Traces RPC implementation.
+use std::sync::Arc;
+
+use ethcore::trace::{AddressesFilter, Filter as EthcoreTraceFilter};
+use ethereum_types::Address;
+use failure::format_err;
+use jsonrpc_core::Result;
+use parity_rpc::v1::types::{BlockNumber, Index, LocalizedTrace, TraceFilter, H256 as RpcH256};
+
+use crate::{blockchain::Blockchain, traits::Traces, util::jsonrpc_error};
+
+/// Traces rpc implementation.
+pub struct TracesClient {
+    blockchain: Arc<Blockchain>,
+}
+
+impl TracesClient {
+    /// Creates new TracesClient.
+    pub fn new(blockchain: Arc<Blockchain>) -> Self {
+        TracesClient { blockchain }
+    }
+
+    fn check_tracing(&self) -> Result<()> {
+        match self.blockchain.tracing() {
+            true => Ok(()),
+            false => Err(jsonrpc_error(format_err!(
+                "Tracing is not enabled, restart with --tracing"
+            ))),
+        }
+    }
+
+    fn block_number(&self, number: BlockNumber) -> u64 {
+        match number {
+            BlockNumber::Num(number) => number,
+            BlockNumber::Earliest => 0,
+            BlockNumber::Latest | BlockNumber::Pending => self.blockchain.best_block_number(),
+        }
+    }
+}
+
+impl Traces for TracesClient {
+    fn filter(&self, filter: TraceFilter) -> Result<Option<Vec<LocalizedTrace>>> {
+        self.check_tracing()?;
+
+        let from_block = self.block_number(filter.from_block.unwrap_or(BlockNumber::Latest));
+        let to_block = self.block_number(filter.to_block.unwrap_or(BlockNumber::Latest));
+        let addresses = |addresses: Option<Vec<_>>| -> AddressesFilter {
+            addresses
+                .unwrap_or_default()
+                .into_iter()
+                .map(Into::into)
+                .collect::<Vec<Address>>()
+                .into()
+        };
+        let ethcore_filter = EthcoreTraceFilter {
+            range: from_block as usize..to_block as usize + 1,
+            from_address: addresses(filter.from_address),
+            to_address: addresses(filter.to_address),
+        };
+
+        Ok(Some(
+            self.blockchain
+                .filter_traces(ethcore_filter)
+                .into_iter()
+                .skip(filter.after.unwrap_or(0))
+                .take(filter.count.unwrap_or(usize::max_value()))
+                .map(Into::into)
+                .collect(),
+        ))
+    }
+
+    fn trace(&self, hash: RpcH256, address: Vec<Index>) -> Result<Option<LocalizedTrace>> {
+        self.check_tracing()?;
+
+        let address: Vec<usize> = address.iter().map(Index::value).collect();
+
+        Ok(self
+            .blockchain
+            .transaction_traces(hash.into())
+            .and_then(|traces| {
+                traces
+                    .into_iter()
+                    .find(|trace| trace.trace_address == address)
+            })
+            .map(Into::into))
+    }
+
+    fn transaction_traces(&self, hash: RpcH256) -> Result<Option<Vec<LocalizedTrace>>> {
+        self.check_tracing()?;
+
+        Ok(self
+            .blockchain
+            .transaction_traces(hash.into())
+            .map(|traces| traces.into_iter().map(Into::into).collect()))
+    }
+
+    fn block_traces(&self, number: BlockNumber) -> Result<Option<Vec<LocalizedTrace>>> {
+        self.check_tracing()?;
+
+        Ok(self
+            .blockchain
+            .block_traces(self.block_number(number))
+            .map(|traces| traces.into_iter().map(Into::into).collect()))
+    }
+}
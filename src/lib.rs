@@ -25,6 +25,7 @@ extern crate parking_lot;
 #[macro_use]
 extern crate serde_derive;
 extern crate jsonrpc_core;
+extern crate serde_json;
 #[macro_use]
 extern crate jsonrpc_macros;
 extern crate ethcore;
@@ -37,14 +38,18 @@ extern crate jsonrpc_ws_server;
 extern crate keccak_hash as hash;
 extern crate parity_reactor;
 extern crate parity_rpc;
+extern crate rlp;
+extern crate sled;
 extern crate tokio;
 extern crate tokio_threadpool;
+extern crate triehash;
 extern crate zeroize;
 
 extern crate ekiden_crypto;
 extern crate ekiden_keymanager;
 
 mod blockchain;
+mod clock;
 mod confidential;
 mod genesis;
 mod impls;
@@ -52,6 +57,7 @@ mod informant;
 mod middleware;
 mod parity;
 mod pubsub;
+mod queue;
 mod rpc;
 mod rpc_apis;
 mod run;
@@ -64,12 +70,18 @@ use std::sync::Arc;
 
 use clap::ArgMatches;
 use ethereum_types::U256;
-use failure::Fallible;
+use failure::{format_err, Fallible};
 
-use ekiden_keymanager::client::MockClient;
+use ekiden_keymanager::{
+    client::{KeyManagerClient, MockClient, SocketClient},
+    store::KeyStore,
+};
+
+use self::confidential::Epochs;
 
 pub use self::{
     blockchain::{BLOCK_GAS_LIMIT, MIN_GAS_PRICE_GWEI},
+    queue::QueueLimits,
     run::RunningGateway,
 };
 
@@ -83,8 +95,56 @@ pub fn start(
     ws_max_connections: usize,
     gas_price: U256,
     block_gas_limit: U256,
+    block_interval_secs: Option<u64>,
+    queue_limits: QueueLimits,
+    data_dir: Option<&str>,
+    state_history: Option<u64>,
+    rpc_apis: &str,
+    tracing: bool,
+    key_manager_socket: Option<&str>,
+    km_master_secret: Option<&str>,
+    km_state_file: Option<&str>,
+    km_passphrase: Option<&str>,
+    epoch_interval: Option<u64>,
+    epoch_grace: Option<u64>,
 ) -> Fallible<RunningGateway> {
-    let km_client = Arc::new(MockClient::new());
+    let epochs = match (epoch_interval, epoch_grace) {
+        (Some(interval), grace) => Some(Epochs::new(interval, grace.unwrap_or(interval))?),
+        (None, Some(_)) => {
+            return Err(format_err!(
+                "An epoch grace window requires an epoch interval"
+            ))
+        }
+        (None, None) => None,
+    };
+
+    let km_client: Arc<dyn KeyManagerClient> = match key_manager_socket {
+        Some(_) if km_master_secret.is_some() || km_state_file.is_some() => {
+            return Err(format_err!(
+                "A master secret or state file cannot be used with an external key manager"
+            ))
+        }
+        Some(path) => Arc::new(SocketClient::new(path)?),
+        None => {
+            let master_secret = km_master_secret.map(|secret| secret.as_bytes().to_vec());
+            match (km_state_file, km_passphrase) {
+                (Some(path), Some(passphrase)) => Arc::new(MockClient::open(
+                    KeyStore::new(path, passphrase),
+                    master_secret,
+                )?),
+                (Some(_), None) => {
+                    return Err(format_err!(
+                        "A passphrase is required to use a key manager state file \
+                         (from --km-passphrase-file or OASIS_CHAIN_KM_PASSPHRASE)"
+                    ))
+                }
+                (None, _) => match master_secret {
+                    Some(master_secret) => Arc::new(MockClient::with_master_secret(master_secret)),
+                    None => Arc::new(MockClient::new()),
+                },
+            }
+        }
+    };
 
     run::execute(
         km_client,
@@ -96,5 +156,12 @@ pub fn start(
         ws_max_connections,
         gas_price,
         block_gas_limit,
+        block_interval_secs,
+        queue_limits,
+        data_dir,
+        state_history,
+        rpc_apis,
+        tracing,
+        epochs,
     )
 }
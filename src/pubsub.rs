@@ -9,6 +9,7 @@ use std::{
 };
 
 use ethcore::filter::TxEntry;
+use ethereum_types::H256;
 use futures::prelude::*;
 use log::error;
 use tokio::timer::Interval;
@@ -22,6 +23,8 @@ pub trait Listener: Send + Sync {
     fn notify_blocks(&self, from_block: u64, to_block: u64);
 
     fn notify_completed_transaction(&self, entry: &TxEntry, output: Vec<u8>);
+
+    fn notify_pending_transactions(&self, hashes: &[H256]);
 }
 
 struct Inner {
@@ -56,6 +59,26 @@ impl Broker {
         Interval::new_interval(interval)
             .map_err(Into::into)
             .for_each(move |_| {
+                // Notify all listeners of newly submitted transactions.
+                let pending = inner.blockchain.take_new_pending_transactions();
+                if !pending.is_empty() {
+                    for listener in inner.listeners.read().unwrap().iter() {
+                        if let Some(listener) = listener.upgrade() {
+                            listener.notify_pending_transactions(&pending);
+                        }
+                    }
+                }
+
+                // If the chain has been reverted, blocks after the revert point
+                // have been replaced and must be notified again.
+                if let Some(reverted_to) = inner.blockchain.take_reverted_to() {
+                    if reverted_to < inner.last_notified_block.load(Ordering::SeqCst) {
+                        inner
+                            .last_notified_block
+                            .store(reverted_to, Ordering::SeqCst);
+                    }
+                }
+
                 // Get latest block and notify all listeners of the difference.
                 let inner = inner.clone();
                 inner.blockchain.get_latest_block().map(move |blk| {
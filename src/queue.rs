@@ -0,0 +1,229 @@
+//! Queue of transactions with future nonces.
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::{Duration, Instant},
+};
+
+use ethcore::transaction::SignedTransaction;
+use ethereum_types::{Address, H256, U256};
+use failure::{format_err, Fallible};
+
+/// Limits on the transactions held in the queue.
+#[derive(Clone, Copy, Debug)]
+pub struct QueueLimits {
+    /// Maximum number of queued transactions per sender.
+    pub per_sender: usize,
+    /// Maximum number of queued transactions in total.
+    pub total: usize,
+    /// Time after which a queued transaction is dropped.
+    pub lifetime: Duration,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            per_sender: 16,
+            total: 1024,
+            lifetime: Duration::from_secs(600),
+        }
+    }
+}
+
+struct Entry<T> {
+    txn: SignedTransaction,
+    inserted: Instant,
+    data: T,
+}
+
+/// Transactions waiting for a gap in their sender's nonces to be filled.
+///
+/// Each transaction carries arbitrary data (e.g., a channel on which to
+/// report its execution result once it is released).
+pub struct TransactionQueue<T> {
+    limits: QueueLimits,
+    senders: HashMap<Address, BTreeMap<U256, Entry<T>>>,
+    len: usize,
+}
+
+impl<T> TransactionQueue<T> {
+    pub fn new(limits: QueueLimits) -> Self {
+        Self {
+            limits,
+            senders: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Queue a transaction.
+    ///
+    /// A transaction from the same sender with the same nonce is replaced,
+    /// and its data returned.
+    pub fn insert(&mut self, txn: SignedTransaction, data: T) -> Fallible<Option<T>> {
+        let sender = txn.sender();
+        let nonce = txn.nonce;
+        let queued = self.senders.entry(sender).or_insert_with(BTreeMap::new);
+
+        if !queued.contains_key(&nonce) {
+            if queued.len() >= self.limits.per_sender {
+                return Err(format_err!(
+                    "Too many queued transactions from sender {:?}",
+                    sender
+                ));
+            }
+            if self.len >= self.limits.total {
+                return Err(format_err!("Transaction queue is full"));
+            }
+        }
+
+        let entry = Entry {
+            txn,
+            inserted: Instant::now(),
+            data,
+        };
+        match queued.insert(nonce, entry) {
+            Some(replaced) => Ok(Some(replaced.data)),
+            None => {
+                self.len += 1;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Remove the transaction with the given sender and nonce, if queued.
+    pub fn take(&mut self, sender: &Address, nonce: &U256) -> Option<(SignedTransaction, T)> {
+        let (entry, empty) = {
+            let queued = self.senders.get_mut(sender)?;
+            (queued.remove(nonce)?, queued.is_empty())
+        };
+        if empty {
+            self.senders.remove(sender);
+        }
+        self.len -= 1;
+
+        Some((entry.txn, entry.data))
+    }
+
+    /// Remove transactions which have been queued for longer than the
+    /// configured lifetime.
+    pub fn cull(&mut self, now: Instant) -> Vec<(SignedTransaction, T)> {
+        let lifetime = self.limits.lifetime;
+        let expired: Vec<(Address, U256)> = self
+            .senders
+            .iter()
+            .flat_map(|(sender, queued)| {
+                queued
+                    .iter()
+                    .filter(|(_, entry)| now.duration_since(entry.inserted) >= lifetime)
+                    .map(move |(nonce, _)| (*sender, *nonce))
+            })
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|(sender, nonce)| self.take(&sender, &nonce))
+            .collect()
+    }
+
+    /// Remove all queued transactions.
+    pub fn clear(&mut self) -> Vec<(SignedTransaction, T)> {
+        self.len = 0;
+        self.senders
+            .drain()
+            .flat_map(|(_, queued)| queued.into_iter())
+            .map(|(_, entry)| (entry.txn, entry.data))
+            .collect()
+    }
+
+    /// Look up a queued transaction by its hash.
+    pub fn get(&self, hash: &H256) -> Option<&SignedTransaction> {
+        self.senders
+            .values()
+            .flat_map(|queued| queued.values())
+            .map(|entry| &entry.txn)
+            .find(|txn| txn.hash() == *hash)
+    }
+
+    /// Hashes of all queued transactions.
+    pub fn hashes(&self) -> Vec<H256> {
+        self.senders
+            .values()
+            .flat_map(|queued| queued.values())
+            .map(|entry| entry.txn.hash())
+            .collect()
+    }
+
+    /// Number of queued transactions.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethcore::transaction::{Action, Transaction};
+
+    use super::*;
+
+    fn txn(sender: u64, nonce: u64) -> SignedTransaction {
+        Transaction {
+            nonce: nonce.into(),
+            gas_price: U256::zero(),
+            gas: 21_000.into(),
+            action: Action::Call(Address::zero()),
+            value: U256::zero(),
+            data: vec![],
+        }
+        .fake_sign(Address::from(sender))
+    }
+
+    #[test]
+    fn test_queue_take() {
+        let mut queue = TransactionQueue::new(QueueLimits::default());
+        queue.insert(txn(1, 3), "a").unwrap();
+        queue.insert(txn(1, 2), "b").unwrap();
+        queue.insert(txn(2, 2), "c").unwrap();
+        assert_eq!(queue.len(), 3);
+
+        let hash = txn(1, 3).hash();
+        assert_eq!(queue.get(&hash).map(|txn| txn.nonce), Some(3.into()));
+
+        assert!(queue.take(&Address::from(1), &1.into()).is_none());
+        assert_eq!(queue.take(&Address::from(1), &2.into()).unwrap().1, "b");
+        assert_eq!(queue.take(&Address::from(1), &3.into()).unwrap().1, "a");
+        assert!(queue.get(&hash).is_none());
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_queue_limits() {
+        let mut queue = TransactionQueue::new(QueueLimits {
+            per_sender: 2,
+            total: 3,
+            lifetime: Duration::from_secs(60),
+        });
+        queue.insert(txn(1, 1), "a").unwrap();
+        queue.insert(txn(1, 2), "b").unwrap();
+        assert!(queue.insert(txn(1, 3), "c").is_err());
+
+        // Replacing a queued transaction does not count against the limits.
+        assert_eq!(queue.insert(txn(1, 2), "d").unwrap(), Some("b"));
+
+        queue.insert(txn(2, 1), "e").unwrap();
+        assert!(queue.insert(txn(3, 1), "f").is_err());
+        assert_eq!(queue.len(), 3);
+    }
+
+    #[test]
+    fn test_queue_cull() {
+        let mut queue = TransactionQueue::new(QueueLimits {
+            lifetime: Duration::from_secs(60),
+            ..Default::default()
+        });
+        queue.insert(txn(1, 1), "a").unwrap();
+
+        assert!(queue.cull(Instant::now()).is_empty());
+        let expired = queue.cull(Instant::now() + Duration::from_secs(60));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(queue.len(), 0);
+    }
+}
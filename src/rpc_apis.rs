@@ -16,15 +16,15 @@
 
 use std::{cmp::PartialEq, collections::HashSet, str::FromStr, sync::Arc};
 
-use ekiden_keymanager::client::MockClient;
+use ekiden_keymanager::client::KeyManagerClient;
 use jsonrpc_core::{self as core, MetaIoHandler};
 use parity_rpc::{informant::ActivityNotifier, Host, Metadata};
 
 use crate::{
     blockchain::Blockchain,
     impls::{
-        EthClient, EthFilterClient, EthPubSubClient, EthSigningClient, NetClient, OasisClient,
-        Web3Client,
+        DebugClient, EthClient, EthFilterClient, EthPubSubClient, EthSigningClient, EvmClient,
+        NetClient, OasisClient, OasisDevClient, TracesClient, Web3Client,
     },
     pubsub::Broker,
 };
@@ -41,6 +41,14 @@ pub enum Api {
     EthPubSub,
     /// Oasis (Safe)
     Oasis,
+    /// EVM development methods, e.g. chain snapshots (Unsafe)
+    Evm,
+    /// Debug, e.g. transaction tracing (Unsafe)
+    Debug,
+    /// Traces of mined transactions (Unsafe)
+    Traces,
+    /// Oasis development methods, e.g. contract key export (Unsafe)
+    OasisDev,
 }
 
 impl FromStr for Api {
@@ -55,6 +63,10 @@ impl FromStr for Api {
             "eth" => Ok(Eth),
             "pubsub" => Ok(EthPubSub),
             "oasis" => Ok(Oasis),
+            "evm" => Ok(Evm),
+            "debug" => Ok(Debug),
+            "traces" => Ok(Traces),
+            "oasis_dev" => Ok(OasisDev),
             api => Err(format!("Unknown api: {}", api)),
         }
     }
@@ -146,7 +158,7 @@ pub trait Dependencies {
 pub struct FullDependencies {
     pub blockchain: Arc<Blockchain>,
     pub broker: Arc<Broker>,
-    pub km_client: Arc<MockClient>,
+    pub km_client: Arc<dyn KeyManagerClient>,
     pub ws_address: Option<Host>,
 }
 
@@ -160,7 +172,7 @@ impl FullDependencies {
         S: core::Middleware<Metadata>,
     {
         use parity_rpc::v1::{Eth, EthFilter, EthPubSub, EthSigning, Net, Web3};
-        use traits::Oasis;
+        use traits::{Debug, Evm, Oasis, OasisDev, Traces};
 
         for api in apis {
             match *api {
@@ -174,7 +186,16 @@ impl FullDependencies {
                     let client = EthClient::new(self.blockchain.clone());
                     handler.extend_with(client.to_delegate());
 
-                    let signing_client = EthSigningClient::new();
+                    // Replace eth_call with a handler which also accepts state overrides.
+                    let call_client = EthClient::new(self.blockchain.clone());
+                    handler.add_method_with_meta(
+                        "eth_call",
+                        move |params: core::Params, meta: Metadata| {
+                            call_client.call_with_params(params, meta)
+                        },
+                    );
+
+                    let signing_client = EthSigningClient::new(self.blockchain.clone());
                     handler.extend_with(signing_client.to_delegate());
 
                     if !for_generic_pubsub {
@@ -195,6 +216,21 @@ impl FullDependencies {
                             .to_delegate(),
                     );
                 }
+                Api::Evm => {
+                    handler.extend_with(EvmClient::new(self.blockchain.clone()).to_delegate());
+                }
+                Api::Debug => {
+                    handler.extend_with(DebugClient::new(self.blockchain.clone()).to_delegate());
+                }
+                Api::Traces => {
+                    handler.extend_with(TracesClient::new(self.blockchain.clone()).to_delegate());
+                }
+                Api::OasisDev => {
+                    handler.extend_with(
+                        OasisDevClient::new(self.blockchain.clone(), self.km_client.clone())
+                            .to_delegate(),
+                    );
+                }
             }
         }
     }
@@ -229,7 +265,14 @@ impl ApiSet {
             ApiSet::UnsafeContext => public_list,
             #[cfg(test)]
             ApiSet::SafeContext => public_list,
-            ApiSet::All => public_list,
+            ApiSet::All => {
+                let mut all_list = public_list;
+                all_list.insert(Api::Evm);
+                all_list.insert(Api::Debug);
+                all_list.insert(Api::Traces);
+                all_list.insert(Api::OasisDev);
+                all_list
+            }
         }
     }
 }
@@ -245,6 +288,10 @@ mod test {
         assert_eq!(Api::Eth, "eth".parse().unwrap());
         assert_eq!(Api::EthPubSub, "pubsub".parse().unwrap());
         assert_eq!(Api::Oasis, "oasis".parse().unwrap());
+        assert_eq!(Api::Evm, "evm".parse().unwrap());
+        assert_eq!(Api::Debug, "debug".parse().unwrap());
+        assert_eq!(Api::Traces, "traces".parse().unwrap());
+        assert_eq!(Api::OasisDev, "oasis_dev".parse().unwrap());
         assert!("rp".parse::<Api>().is_err());
     }
 
@@ -296,9 +343,19 @@ mod test {
         assert_eq!(
             "all".parse::<ApiSet>().unwrap(),
             ApiSet::List(
-                vec![Api::Web3, Api::Net, Api::Eth, Api::EthPubSub, Api::Oasis,]
-                    .into_iter()
-                    .collect()
+                vec![
+                    Api::Web3,
+                    Api::Net,
+                    Api::Eth,
+                    Api::EthPubSub,
+                    Api::Oasis,
+                    Api::Evm,
+                    Api::Debug,
+                    Api::Traces,
+                    Api::OasisDev,
+                ]
+                .into_iter()
+                .collect()
             )
         );
     }
@@ -20,18 +20,27 @@ use std::{
     time::{Duration, Instant},
 };
 
-use ekiden_keymanager::client::MockClient;
+use ekiden_keymanager::client::KeyManagerClient;
 use ethereum_types::U256;
 use failure::{format_err, Fallible};
+use futures::{future, prelude::*};
 use informant;
-use log::{info, warn};
+use log::{error, info, warn};
 use rpc::{self, HttpConfiguration, WsConfiguration};
 use rpc_apis;
-
-use crate::{blockchain::Blockchain, pubsub::Broker};
+use tokio::timer::Interval;
+use tokio_threadpool::blocking;
+
+use crate::{
+    blockchain::{Blockchain, MiningMode},
+    confidential::Epochs,
+    pubsub::Broker,
+    queue::QueueLimits,
+    storage::{DiskStore, KeyValueStore, MemoryStore},
+};
 
 pub fn execute(
-    km_client: Arc<MockClient>,
+    km_client: Arc<dyn KeyManagerClient>,
     pubsub_interval_secs: u64,
     interface: &str,
     http_port: u16,
@@ -40,17 +49,69 @@ pub fn execute(
     ws_max_connections: usize,
     gas_price: U256,
     block_gas_limit: U256,
+    block_interval_secs: Option<u64>,
+    queue_limits: QueueLimits,
+    data_dir: Option<&str>,
+    state_history: Option<u64>,
+    rpc_apis: &str,
+    tracing: bool,
+    epochs: Option<Epochs>,
 ) -> Fallible<RunningGateway> {
+    let apis: rpc_apis::ApiSet = rpc_apis.parse().map_err(|err| format_err!("{}", err))?;
+
     let mut runtime = tokio::runtime::Runtime::new()?;
 
+    let store: Arc<dyn KeyValueStore> = match data_dir {
+        Some(data_dir) => {
+            info!("Using chain database in {}", data_dir);
+            Arc::new(DiskStore::open(data_dir)?)
+        }
+        None => Arc::new(MemoryStore::new()),
+    };
+
+    let mining_mode = match block_interval_secs {
+        Some(secs) if secs > 0 => {
+            info!("Sealing blocks every {} seconds", secs);
+            MiningMode::Interval(Duration::new(secs, 0))
+        }
+        _ => MiningMode::Instant,
+    };
+
     let blockchain = Arc::new(Blockchain::new(
         gas_price,
         block_gas_limit,
+        mining_mode,
+        queue_limits,
         km_client.clone(),
-    ));
+        store,
+        state_history,
+        tracing,
+        epochs,
+    )?);
     let broker = Arc::new(Broker::new(blockchain.clone()));
     runtime.spawn(broker.start(Duration::new(pubsub_interval_secs, 0)));
 
+    if let MiningMode::Interval(interval) = mining_mode {
+        let blockchain = blockchain.clone();
+        runtime.spawn(
+            Interval::new_interval(interval)
+                .map_err(|err| error!("Block sealing timer error: {:?}", err))
+                .for_each(move |_| {
+                    // Mining executes the pending transactions under the chain
+                    // lock, so keep it off the runtime's workers.
+                    let blockchain = blockchain.clone();
+                    future::poll_fn(move || blocking(|| blockchain.mine_pending())).then(|result| {
+                        match result {
+                            Ok(Err(err)) => error!("Failed to seal pending transactions: {}", err),
+                            Err(err) => error!("Failed to seal pending transactions: {}", err),
+                            Ok(Ok(())) => {}
+                        }
+                        Ok(())
+                    })
+                }),
+        );
+    }
+
     let rpc_stats = Arc::new(informant::RpcStats::default());
 
     // Conf corresponds to parity command-line options "--unsafe-expose" + "--jsonrpc-cors=all"
@@ -59,6 +120,7 @@ pub fn execute(
     ws_conf.hosts = None;
     ws_conf.interface = interface.into();
     ws_conf.port = ws_port;
+    ws_conf.apis = apis.clone();
 
     // Max # of concurrent connections. the default is 100, which is "low" and "should be increased":
     // https://github.com/tomusdrw/ws-rs/blob/f12d19c4c19422fc79af28a3181f598bc07ecd1e/src/lib.rs#L128
@@ -70,6 +132,7 @@ pub fn execute(
     http_conf.interface = interface.into();
     http_conf.port = http_port;
     http_conf.server_threads = num_threads;
+    http_conf.apis = apis;
 
     // Define RPC handlers.
     let deps_for_rpc_apis = Arc::new(rpc_apis::FullDependencies {
@@ -108,7 +171,7 @@ pub fn execute(
 pub struct RunningGateway {
     runtime: tokio::runtime::Runtime,
     blockchain: Arc<Blockchain>,
-    km_client: Arc<MockClient>,
+    km_client: Arc<dyn KeyManagerClient>,
     http_server: Option<jsonrpc_http_server::Server>,
     ws_server: Option<jsonrpc_ws_server::Server>,
 }
@@ -1,39 +1,891 @@
 //! Storage wrappers.
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::{Arc, Mutex, RwLock},
 };
 
 use ethcore::mkvs::MKVS;
+use failure::{format_err, Fallible};
+use rlp::{DecoderError, Rlp};
+
+/// Logical column of the chain database.
+///
+/// Each key is prefixed with its column identifier so that all columns can
+/// share a single key space and be updated atomically.
+#[derive(Clone, Copy, Debug)]
+pub enum Column {
+    /// MKVS contents (accounts, code and storage).
+    State = 0,
+    /// Encoded blocks, keyed by block hash.
+    Blocks = 1,
+    /// Block hashes, keyed by big-endian block number.
+    BlockIndex = 2,
+    /// Chain metadata (e.g., the best block number).
+    Extra = 3,
+    /// MKVS keys written by each block, keyed by big-endian block number.
+    StateJournal = 4,
+    /// Transaction traces of each block mined with tracing enabled, keyed by
+    /// block hash.
+    Traces = 5,
+}
+
+impl Column {
+    fn key(self, key: &[u8]) -> Vec<u8> {
+        let mut prefixed = Vec::with_capacity(key.len() + 1);
+        prefixed.push(self as u8);
+        prefixed.extend_from_slice(key);
+        prefixed
+    }
+
+    /// Prefixed bounds of the key range `[start, end)`, which extends to the
+    /// end of the column if `end` is `None`.
+    fn range(self, start: &[u8], end: Option<&[u8]>) -> (Vec<u8>, Vec<u8>) {
+        let end = match end {
+            Some(end) => self.key(end),
+            None => vec![self as u8 + 1],
+        };
+        (self.key(start), end)
+    }
+}
+
+/// A set of updates to be applied atomically.
+#[derive(Default)]
+pub struct WriteBatch {
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a key to a new value.
+    pub fn put(&mut self, column: Column, key: &[u8], value: &[u8]) {
+        self.ops.push((column.key(key), Some(value.to_vec())));
+    }
+
+    /// Remove a key.
+    pub fn delete(&mut self, column: Column, key: &[u8]) {
+        self.ops.push((column.key(key), None));
+    }
+}
+
+/// Backing key/value store for the chain database.
+pub trait KeyValueStore: Send + Sync {
+    /// Fetch the value stored under `key` in `column`.
+    fn get(&self, column: Column, key: &[u8]) -> Fallible<Option<Vec<u8>>>;
+
+    /// Fetch the entries of `column` with keys in `[start, end)`, in
+    /// ascending key order. If `end` is `None`, the range extends to the end
+    /// of the column.
+    fn range(
+        &self,
+        column: Column,
+        start: &[u8],
+        end: Option<&[u8]>,
+    ) -> Fallible<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Fetch the last entry of `column` with a key in `[start, end)`.
+    fn last_in_range(
+        &self,
+        column: Column,
+        start: &[u8],
+        end: &[u8],
+    ) -> Fallible<Option<(Vec<u8>, Vec<u8>)>>;
+
+    /// Atomically apply a batch of updates.
+    fn write(&self, batch: WriteBatch) -> Fallible<()>;
+
+    /// Ensure all previous writes are durable.
+    fn flush(&self) -> Fallible<()> {
+        Ok(())
+    }
+}
 
 /// In-memory trivial key/value storage.
 #[derive(Clone)]
-pub struct MemoryMKVS(Arc<RwLock<HashMap<Vec<u8>, Vec<u8>>>>);
+pub struct MemoryStore(Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>);
 
-impl MemoryMKVS {
+impl MemoryStore {
     pub fn new() -> Self {
-        MemoryMKVS(Arc::new(RwLock::new(HashMap::new())))
+        MemoryStore(Arc::new(RwLock::new(BTreeMap::new())))
     }
 }
 
-impl MKVS for MemoryMKVS {
-    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.0.read().unwrap().get(key).map(|v| v.clone())
+impl KeyValueStore for MemoryStore {
+    fn get(&self, column: Column, key: &[u8]) -> Fallible<Option<Vec<u8>>> {
+        Ok(self.0.read().unwrap().get(&column.key(key)).cloned())
     }
 
-    fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+    fn range(
+        &self,
+        column: Column,
+        start: &[u8],
+        end: Option<&[u8]>,
+    ) -> Fallible<Vec<(Vec<u8>, Vec<u8>)>> {
+        let (start, end) = column.range(start, end);
+        if start >= end {
+            return Ok(vec![]);
+        }
+
+        Ok(self
+            .0
+            .read()
+            .unwrap()
+            .range(start..end)
+            .map(|(key, value)| (key[1..].to_vec(), value.clone()))
+            .collect())
+    }
+
+    fn last_in_range(
+        &self,
+        column: Column,
+        start: &[u8],
+        end: &[u8],
+    ) -> Fallible<Option<(Vec<u8>, Vec<u8>)>> {
+        let (start, end) = column.range(start, Some(end));
+        if start >= end {
+            return Ok(None);
+        }
+
+        Ok(self
+            .0
+            .read()
+            .unwrap()
+            .range(start..end)
+            .next_back()
+            .map(|(key, value)| (key[1..].to_vec(), value.clone())))
+    }
+
+    fn write(&self, batch: WriteBatch) -> Fallible<()> {
+        let mut map = self.0.write().unwrap();
+        for (key, value) in batch.ops {
+            match value {
+                Some(value) => map.insert(key, value),
+                None => map.remove(&key),
+            };
+        }
+
+        Ok(())
+    }
+}
+
+/// Persistent on-disk key/value storage.
+pub struct DiskStore(sled::Db);
+
+impl DiskStore {
+    /// Open (or create) a database in the given directory.
+    pub fn open<P: AsRef<Path>>(path: P) -> Fallible<Self> {
+        Ok(DiskStore(sled::open(path)?))
+    }
+}
+
+impl KeyValueStore for DiskStore {
+    fn get(&self, column: Column, key: &[u8]) -> Fallible<Option<Vec<u8>>> {
+        Ok(self.0.get(column.key(key))?.map(|v| v.to_vec()))
+    }
+
+    fn range(
+        &self,
+        column: Column,
+        start: &[u8],
+        end: Option<&[u8]>,
+    ) -> Fallible<Vec<(Vec<u8>, Vec<u8>)>> {
+        let (start, end) = column.range(start, end);
+        if start >= end {
+            return Ok(vec![]);
+        }
+
         self.0
-            .write()
+            .range(start..end)
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((key[1..].to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    fn last_in_range(
+        &self,
+        column: Column,
+        start: &[u8],
+        end: &[u8],
+    ) -> Fallible<Option<(Vec<u8>, Vec<u8>)>> {
+        let (start, end) = column.range(start, Some(end));
+        if start >= end {
+            return Ok(None);
+        }
+
+        match self.0.range(start..end).next_back() {
+            Some(entry) => {
+                let (key, value) = entry?;
+                Ok(Some((key[1..].to_vec(), value.to_vec())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn write(&self, batch: WriteBatch) -> Fallible<()> {
+        let mut sled_batch = sled::Batch::default();
+        for (key, value) in batch.ops {
+            match value {
+                Some(value) => sled_batch.insert(key, value),
+                None => sled_batch.remove(key),
+            }
+        }
+        self.0.apply_batch(sled_batch)?;
+
+        Ok(())
+    }
+
+    fn flush(&self) -> Fallible<()> {
+        self.0.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Key under which a version of an MKVS key is stored in the state column:
+/// the length of the MKVS key, the key itself and the big-endian version.
+///
+/// The versions of a key are thus adjacent and ordered, so that its value as
+/// of any version is found with a single reverse seek.
+fn version_key(key: &[u8], version: u64) -> Vec<u8> {
+    let mut versioned = Vec::with_capacity(4 + key.len() + 8);
+    versioned.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    versioned.extend_from_slice(key);
+    versioned.extend_from_slice(&version.to_be_bytes());
+    versioned
+}
+
+/// Split a state column key into its MKVS key and version.
+fn split_version_key(versioned: &[u8]) -> Fallible<(Vec<u8>, u64)> {
+    if versioned.len() < 4 + 8 {
+        return Err(format_err!("malformed state key"));
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&versioned[..4]);
+    let len = u32::from_be_bytes(len) as usize;
+    if versioned.len() != 4 + len + 8 {
+        return Err(format_err!("malformed state key"));
+    }
+    let mut version = [0u8; 8];
+    version.copy_from_slice(&versioned[4 + len..]);
+
+    Ok((versioned[4..4 + len].to_vec(), u64::from_be_bytes(version)))
+}
+
+/// Encode the value of a version, where `None` marks the key as removed.
+fn encode_value(value: &Option<Vec<u8>>) -> Vec<u8> {
+    match value {
+        Some(value) => {
+            let mut encoded = Vec::with_capacity(value.len() + 1);
+            encoded.push(1);
+            encoded.extend_from_slice(value);
+            encoded
+        }
+        None => vec![0],
+    }
+}
+
+fn decode_value(data: &[u8]) -> Fallible<Option<Vec<u8>>> {
+    match data.split_first() {
+        Some((&1, value)) => Ok(Some(value.to_vec())),
+        Some((&0, rest)) if rest.is_empty() => Ok(None),
+        _ => Err(format_err!("malformed state value")),
+    }
+}
+
+/// Versions of an MKVS key as stored before each version had its own key:
+/// RLP-encoded under the MKVS key itself, ordered by ascending version.
+struct History(Vec<(u64, Option<Vec<u8>>)>);
+
+impl History {
+    fn decode(data: &[u8]) -> Result<Self, DecoderError> {
+        let mut entries = vec![];
+        for entry in Rlp::new(data).iter() {
+            let version = entry.val_at(0)?;
+            let value = match entry.item_count()? {
+                1 => None,
+                _ => Some(entry.val_at(1)?),
+            };
+            entries.push((version, value));
+        }
+
+        Ok(History(entries))
+    }
+}
+
+/// Final values of the MKVS keys written by a block (`None` if removed).
+pub type WriteSet = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// MKVS writes of a block being mined, buffered until the block is stored.
+#[derive(Debug, Default)]
+pub struct Journal {
+    /// Final values of the keys written.
+    pub writes: WriteSet,
+    /// First failure to read the store while executing the block, which
+    /// makes the block's state unreliable.
+    pub error: Option<String>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fail if the store could not be read while executing the block.
+    pub fn check(&self) -> Fallible<()> {
+        match self.error {
+            Some(ref error) => Err(format_err!("state read failed: {}", error)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Versioned MKVS backed by the state column of a key/value store.
+///
+/// Every write is tagged with the number of the block that performed it,
+/// so that reads can be served as of any retained block. A view reads the
+/// newest value written at or before its version.
+///
+/// Writes are buffered in the view's journal (shared by its clones) and
+/// only reach the store when the journal is written with `write_state`,
+/// together with the block that performed them.
+#[derive(Clone)]
+pub struct DatabaseMKVS {
+    store: Arc<dyn KeyValueStore>,
+    version: u64,
+    journal: Arc<Mutex<Journal>>,
+}
+
+impl DatabaseMKVS {
+    /// Create a view of the state as of the given block number.
+    pub fn new(store: Arc<dyn KeyValueStore>, version: u64) -> Self {
+        Self {
+            store,
+            version,
+            journal: Arc::new(Mutex::new(Journal::new())),
+        }
+    }
+
+    /// Buffer all writes performed through this view (and its clones) in
+    /// the given journal.
+    pub fn with_journal(mut self, journal: Arc<Mutex<Journal>>) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    fn read(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match read_version(&*self.store, key, self.version) {
+            Ok(value) => value,
+            Err(err) => {
+                // The MKVS interface cannot fail, so record the failure for
+                // the block being mined and read the key as missing.
+                error!("Failed to read state: {}", err);
+                self.journal
+                    .lock()
+                    .unwrap()
+                    .error
+                    .get_or_insert(err.to_string());
+                None
+            }
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        let previous = self.get(key);
+        self.journal
+            .lock()
             .unwrap()
-            .insert(key.to_vec(), value.to_vec())
-            .map(|v| v.clone())
+            .writes
+            .insert(key.to_vec(), value);
+
+        previous
+    }
+}
+
+impl MKVS for DatabaseMKVS {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(value) = self.journal.lock().unwrap().writes.get(key) {
+            return value.clone();
+        }
+
+        self.read(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        self.set(key, Some(value.to_vec()))
     }
 
     fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
-        self.0.write().unwrap().remove(key).map(|v| v.clone())
+        self.set(key, None)
     }
 
     fn boxed_clone(&self) -> Box<dyn MKVS> {
         Box::new(self.clone())
     }
 }
+
+/// Value of an MKVS key as of the given version.
+fn read_version(store: &dyn KeyValueStore, key: &[u8], version: u64) -> Fallible<Option<Vec<u8>>> {
+    let end = version_key(key, version.saturating_add(1));
+    match store.last_in_range(Column::State, &version_key(key, 0), &end)? {
+        Some((_, data)) => decode_value(&data),
+        None => Ok(None),
+    }
+}
+
+/// Add updates to the batch which apply the MKVS writes of the block with
+/// the given number.
+///
+/// If `history` is set, only that many past block states are retained for
+/// the keys written.
+pub fn write_state(
+    store: &dyn KeyValueStore,
+    batch: &mut WriteBatch,
+    writes: &WriteSet,
+    version: u64,
+    history: Option<u64>,
+) -> Fallible<()> {
+    for (key, value) in writes {
+        if let Some(retained) = history {
+            // Discard the versions which are not needed to serve reads at or
+            // after the oldest retained version.
+            let oldest = version.saturating_sub(retained);
+            let end = version_key(key, oldest.saturating_add(1));
+            let mut stale = store.range(Column::State, &version_key(key, 0), Some(&end[..]))?;
+            if oldest < version {
+                // Keep the newest version visible at the oldest retained one,
+                // unless it is a removal, which reads like a missing key.
+                if let Some((_, data)) = stale.last() {
+                    if decode_value(data)?.is_some() {
+                        stale.pop();
+                    }
+                }
+            }
+            for (stale_key, _) in stale {
+                batch.delete(Column::State, &stale_key);
+            }
+        }
+
+        put_state_version(batch, key, version, value);
+    }
+
+    Ok(())
+}
+
+/// Add updates to the batch which revert an MKVS key to its value as of the
+/// given version, discarding all later versions.
+pub fn revert_state_key(
+    store: &dyn KeyValueStore,
+    batch: &mut WriteBatch,
+    key: &[u8],
+    version: u64,
+) -> Fallible<()> {
+    let start = version_key(key, version.saturating_add(1));
+    let end = version_key(key, u64::max_value());
+    for (later, _) in store.range(Column::State, &start, Some(&end[..]))? {
+        batch.delete(Column::State, &later);
+    }
+
+    Ok(())
+}
+
+/// Every stored version of every MKVS key, ordered by key and ascending
+/// version.
+pub fn state_versions(store: &dyn KeyValueStore) -> Fallible<Vec<(Vec<u8>, u64, Option<Vec<u8>>)>> {
+    store
+        .range(Column::State, &[], None)?
+        .into_iter()
+        .map(|(versioned, data)| {
+            let (key, version) = split_version_key(&versioned)?;
+            Ok((key, version, decode_value(&data)?))
+        })
+        .collect()
+}
+
+/// Add an update to the batch which stores a version of an MKVS key.
+pub fn put_state_version(
+    batch: &mut WriteBatch,
+    key: &[u8],
+    version: u64,
+    value: &Option<Vec<u8>>,
+) {
+    batch.put(
+        Column::State,
+        &version_key(key, version),
+        &encode_value(value),
+    );
+}
+
+/// Add an update to the batch which discards a version of an MKVS key.
+pub fn delete_state_version(batch: &mut WriteBatch, key: &[u8], version: u64) {
+    batch.delete(Column::State, &version_key(key, version));
+}
+
+/// Add updates to the batch which convert the state column from the layout
+/// in which all versions of an MKVS key were RLP-encoded under the key
+/// itself. Returns the number of MKVS keys converted.
+pub fn migrate_state_versions(
+    store: &dyn KeyValueStore,
+    batch: &mut WriteBatch,
+) -> Fallible<usize> {
+    let entries = store.range(Column::State, &[], None)?;
+    let mut versions = vec![];
+    for (key, data) in &entries {
+        let history = History::decode(data).map_err(|err| format_err!("{}", err))?;
+        batch.delete(Column::State, key);
+        for (version, value) in history.0 {
+            versions.push((version_key(key, version), encode_value(&value)));
+        }
+    }
+    // Only write the new keys after deleting all old ones, which they could
+    // coincide with.
+    for (key, value) in versions {
+        batch.put(Column::State, &key, &value);
+    }
+
+    Ok(entries.len())
+}
+
+/// MKVS which keeps writes in memory on top of a database view.
+///
+/// Used for speculative state (e.g., the pending block) which must never be
+/// written to the store. Like the journal of `DatabaseMKVS`, the changes are
+/// shared by clones, as the state writes through cloned handles.
+#[derive(Clone)]
+pub struct OverlayMKVS {
+    base: DatabaseMKVS,
+    changes: Arc<Mutex<HashMap<Vec<u8>, Option<Vec<u8>>>>>,
+}
+
+impl OverlayMKVS {
+    pub fn new(base: DatabaseMKVS) -> Self {
+        Self {
+            base,
+            changes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn set(&mut self, key: &[u8], value: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        let previous = self.get(key);
+        self.changes.lock().unwrap().insert(key.to_vec(), value);
+        previous
+    }
+}
+
+impl MKVS for OverlayMKVS {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(value) = self.changes.lock().unwrap().get(key) {
+            return value.clone();
+        }
+
+        self.base.get(key)
+    }
+
+    fn insert(&mut self, key: &[u8], value: &[u8]) -> Option<Vec<u8>> {
+        self.set(key, Some(value.to_vec()))
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.set(key, None)
+    }
+
+    fn boxed_clone(&self) -> Box<dyn MKVS> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rlp::RlpStream;
+
+    use super::*;
+
+    #[test]
+    fn test_memory_store_columns() {
+        let store = MemoryStore::new();
+        let mut batch = WriteBatch::new();
+        batch.put(Column::Blocks, b"key", b"block");
+        batch.put(Column::Extra, b"key", b"extra");
+        store.write(batch).unwrap();
+
+        assert_eq!(
+            store.get(Column::Blocks, b"key").unwrap(),
+            Some(b"block".to_vec())
+        );
+        assert_eq!(
+            store.get(Column::Extra, b"key").unwrap(),
+            Some(b"extra".to_vec())
+        );
+        assert_eq!(store.get(Column::State, b"key").unwrap(), None);
+
+        let mut batch = WriteBatch::new();
+        batch.delete(Column::Blocks, b"key");
+        store.write(batch).unwrap();
+
+        assert_eq!(store.get(Column::Blocks, b"key").unwrap(), None);
+    }
+
+    /// Store the writes of a block to the state column.
+    fn commit(store: &Arc<dyn KeyValueStore>, version: u64, writes: &[(&str, Option<&str>)]) {
+        let writes: WriteSet = writes
+            .iter()
+            .map(|(key, value)| {
+                (
+                    key.as_bytes().to_vec(),
+                    value.map(|value| value.as_bytes().to_vec()),
+                )
+            })
+            .collect();
+        let mut batch = WriteBatch::new();
+        write_state(&**store, &mut batch, &writes, version, None).unwrap();
+        store.write(batch).unwrap();
+    }
+
+    #[test]
+    fn test_database_mkvs() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut mkvs = DatabaseMKVS::new(store.clone(), 0);
+
+        assert_eq!(mkvs.insert(b"foo", b"bar"), None);
+        assert_eq!(mkvs.get(b"foo"), Some(b"bar".to_vec()));
+        assert_eq!(mkvs.insert(b"foo", b"baz"), Some(b"bar".to_vec()));
+        assert_eq!(mkvs.remove(b"foo"), Some(b"baz".to_vec()));
+        assert_eq!(mkvs.get(b"foo"), None);
+
+        // Writes are only buffered.
+        mkvs.insert(b"foo", b"bar");
+        assert_eq!(store.get(Column::State, b"foo").unwrap(), None);
+    }
+
+    #[test]
+    fn test_database_mkvs_versions() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+
+        commit(&store, 1, &[("foo", Some("one"))]);
+        commit(&store, 3, &[("foo", Some("three"))]);
+        commit(&store, 5, &[("foo", None)]);
+
+        let at = |version| DatabaseMKVS::new(store.clone(), version).get(b"foo");
+        assert_eq!(at(0), None);
+        assert_eq!(at(1), Some(b"one".to_vec()));
+        assert_eq!(at(2), Some(b"one".to_vec()));
+        assert_eq!(at(3), Some(b"three".to_vec()));
+        assert_eq!(at(4), Some(b"three".to_vec()));
+        assert_eq!(at(5), None);
+    }
+
+    #[test]
+    fn test_write_state_history() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+
+        for version in 1..=4 {
+            let value = vec![version as u8];
+            let writes: WriteSet = vec![(b"foo".to_vec(), Some(value))].into_iter().collect();
+            let mut batch = WriteBatch::new();
+            write_state(&*store, &mut batch, &writes, version, Some(1)).unwrap();
+            store.write(batch).unwrap();
+        }
+
+        let at = |version| DatabaseMKVS::new(store.clone(), version).get(b"foo");
+        assert_eq!(at(2), None);
+        assert_eq!(at(3), Some(vec![3]));
+        assert_eq!(at(4), Some(vec![4]));
+
+        // Only the versions visible at the retained states are stored.
+        assert_eq!(store.range(Column::State, &[], None).unwrap().len(), 2);
+
+        // A removal is discarded once no retained state can see it.
+        for version in 5..=6 {
+            let writes: WriteSet = vec![(b"foo".to_vec(), None)].into_iter().collect();
+            let mut batch = WriteBatch::new();
+            write_state(&*store, &mut batch, &writes, version, Some(1)).unwrap();
+            store.write(batch).unwrap();
+        }
+        assert_eq!(store.range(Column::State, &[], None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_database_mkvs_many_versions() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+
+        // A hot key written by every block, next to a neighbouring key.
+        commit(
+            &store,
+            0,
+            &[("fo", Some("neighbour")), ("fooo", Some("neighbour"))],
+        );
+        for version in 1..=1000u64 {
+            let writes: WriteSet = vec![(b"foo".to_vec(), Some(version.to_be_bytes().to_vec()))]
+                .into_iter()
+                .collect();
+            let mut batch = WriteBatch::new();
+            write_state(&*store, &mut batch, &writes, version, None).unwrap();
+            store.write(batch).unwrap();
+        }
+
+        let at = |version: u64, key: &[u8]| DatabaseMKVS::new(store.clone(), version).get(key);
+        assert_eq!(at(0, b"foo"), None);
+        for version in &[1, 500, 999, 1000] {
+            assert_eq!(at(*version, b"foo"), Some(version.to_be_bytes().to_vec()));
+        }
+        assert_eq!(at(2000, b"foo"), Some(1000u64.to_be_bytes().to_vec()));
+        assert_eq!(at(1000, b"fo"), Some(b"neighbour".to_vec()));
+        assert_eq!(at(1000, b"fooo"), Some(b"neighbour".to_vec()));
+    }
+
+    #[test]
+    fn test_revert_state_key() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+
+        commit(&store, 1, &[("foo", Some("one"))]);
+        commit(&store, 3, &[("foo", Some("three")), ("bar", Some("three"))]);
+
+        let mut batch = WriteBatch::new();
+        revert_state_key(&*store, &mut batch, b"foo", 2).unwrap();
+        revert_state_key(&*store, &mut batch, b"bar", 2).unwrap();
+        store.write(batch).unwrap();
+
+        let mkvs = DatabaseMKVS::new(store.clone(), 3);
+        assert_eq!(mkvs.get(b"foo"), Some(b"one".to_vec()));
+        assert_eq!(mkvs.get(b"bar"), None);
+
+        // Only the first version of `foo` is left.
+        assert_eq!(store.range(Column::State, &[], None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_database_mkvs_journal() {
+        let journal = Arc::new(Mutex::new(Journal::new()));
+        let mut mkvs =
+            DatabaseMKVS::new(Arc::new(MemoryStore::new()), 0).with_journal(journal.clone());
+
+        mkvs.insert(b"foo", b"bar");
+        mkvs.boxed_clone().insert(b"foo", b"baz");
+        mkvs.insert(b"qux", b"quux");
+        mkvs.remove(b"qux");
+        assert_eq!(mkvs.get(b"foo"), Some(b"baz".to_vec()));
+
+        let journal = journal.lock().unwrap();
+        assert_eq!(
+            journal.writes.get(&b"foo"[..]),
+            Some(&Some(b"baz".to_vec()))
+        );
+        assert_eq!(journal.writes.get(&b"qux"[..]), Some(&None));
+        assert!(journal.check().is_ok());
+    }
+
+    #[test]
+    fn test_database_mkvs_read_error() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut batch = WriteBatch::new();
+        batch.put(Column::State, &version_key(b"foo", 0), b"malformed");
+        store.write(batch).unwrap();
+
+        let journal = Arc::new(Mutex::new(Journal::new()));
+        let mkvs = DatabaseMKVS::new(store.clone(), 0).with_journal(journal.clone());
+        assert_eq!(mkvs.get(b"foo"), None);
+        assert!(journal.lock().unwrap().check().is_err());
+    }
+
+    #[test]
+    fn test_overlay_mkvs() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        commit(&store, 0, &[("foo", Some("bar")), ("baz", Some("qux"))]);
+        let base = DatabaseMKVS::new(store.clone(), 0);
+
+        let mut overlay = OverlayMKVS::new(base.clone());
+        assert_eq!(overlay.insert(b"foo", b"new"), Some(b"bar".to_vec()));
+        assert_eq!(overlay.remove(b"baz"), Some(b"qux".to_vec()));
+        assert_eq!(overlay.get(b"foo"), Some(b"new".to_vec()));
+        assert_eq!(overlay.get(b"baz"), None);
+
+        // The underlying store is not modified.
+        assert_eq!(base.get(b"foo"), Some(b"bar".to_vec()));
+        assert_eq!(base.get(b"baz"), Some(b"qux".to_vec()));
+    }
+
+    #[test]
+    fn test_overlay_mkvs_clone() {
+        let overlay = OverlayMKVS::new(DatabaseMKVS::new(Arc::new(MemoryStore::new()), 0));
+
+        // Writes through a clone are seen by the original, and vice versa.
+        let mut clone = overlay.boxed_clone();
+        clone.insert(b"foo", b"bar");
+        assert_eq!(overlay.get(b"foo"), Some(b"bar".to_vec()));
+        overlay.boxed_clone().remove(b"foo");
+        assert_eq!(clone.get(b"foo"), None);
+    }
+
+    #[test]
+    fn test_migrate_state_versions() {
+        let store: Arc<dyn KeyValueStore> = Arc::new(MemoryStore::new());
+        let mut history = RlpStream::new_list(3);
+        history.begin_list(2).append(&1u64).append(&b"one".to_vec());
+        history.begin_list(1).append(&3u64);
+        history
+            .begin_list(2)
+            .append(&5u64)
+            .append(&b"five".to_vec());
+        let mut batch = WriteBatch::new();
+        batch.put(Column::State, b"foo", &history.out());
+        store.write(batch).unwrap();
+
+        let mut batch = WriteBatch::new();
+        assert_eq!(migrate_state_versions(&*store, &mut batch).unwrap(), 1);
+        store.write(batch).unwrap();
+
+        let at = |version| DatabaseMKVS::new(store.clone(), version).get(b"foo");
+        assert_eq!(at(0), None);
+        assert_eq!(at(2), Some(b"one".to_vec()));
+        assert_eq!(at(4), None);
+        assert_eq!(at(5), Some(b"five".to_vec()));
+        assert_eq!(store.get(Column::State, b"foo").unwrap(), None);
+        assert_eq!(
+            state_versions(&*store).unwrap(),
+            vec![
+                (b"foo".to_vec(), 1, Some(b"one".to_vec())),
+                (b"foo".to_vec(), 3, None),
+                (b"foo".to_vec(), 5, Some(b"five".to_vec())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_memory_store_range() {
+        let store = MemoryStore::new();
+        let mut batch = WriteBatch::new();
+        for key in &[&b"a"[..], b"b", b"c"] {
+            batch.put(Column::State, key, key);
+        }
+        batch.put(Column::Blocks, b"d", b"d");
+        store.write(batch).unwrap();
+
+        let keys = |entries: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<Vec<u8>> {
+            entries.into_iter().map(|(key, _)| key).collect()
+        };
+        assert_eq!(
+            keys(store.range(Column::State, b"b", None).unwrap()),
+            vec![b"b".to_vec(), b"c".to_vec()]
+        );
+        assert_eq!(
+            keys(store.range(Column::State, b"a", Some(&b"c"[..])).unwrap()),
+            vec![b"a".to_vec(), b"b".to_vec()]
+        );
+        assert!(store
+            .range(Column::State, b"c", Some(&b"a"[..]))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            store.last_in_range(Column::State, b"a", b"c").unwrap(),
+            Some((b"b".to_vec(), b"b".to_vec()))
+        );
+        assert_eq!(
+            store.last_in_range(Column::State, b"d", b"e").unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! Debug RPC interface.
+use jsonrpc_core::{BoxFuture, Value};
+use jsonrpc_macros::Trailing;
+
+use parity_rpc::v1::types::{BlockNumber, Bytes, CallRequest, H160, H256, U256};
+
+build_rpc_trait! {
+    pub trait Debug {
+        type Metadata;
+
+        /// Re-executes a mined transaction with tracing enabled, and returns
+        /// its trace.
+        #[rpc(name = "debug_traceTransaction")]
+        fn trace_transaction(&self, H256) -> BoxFuture<Option<RpcDebugTrace>>;
+
+        /// Executes a call with tracing enabled, and returns its trace.
+        #[rpc(meta, name = "debug_traceCall")]
+        fn trace_call(&self, Self::Metadata, CallRequest, Trailing<BlockNumber>) -> BoxFuture<RpcDebugTrace>;
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcDebugTrace {
+    /// Call tree, in the format of Geth's call tracer.
+    pub call_trace: RpcCallFrame,
+    /// Instruction-level trace, in the format of Parity's VM trace.
+    pub vm_trace: Value,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RpcCallFrame {
+    /// Type of the call, e.g. CALL, DELEGATECALL or CREATE.
+    #[serde(rename = "type")]
+    pub call_type: String,
+    pub from: H160,
+    /// Callee, or address of the created contract.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<H160>,
+    pub value: U256,
+    /// Gas provided to the call.
+    pub gas: U256,
+    /// Gas used by the call, unless it failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gas_used: Option<U256>,
+    pub input: Bytes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Bytes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Decoded revert reason, if the call reverted with one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
+    /// Calls made by this call, in order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub calls: Vec<RpcCallFrame>,
+}
@@ -0,0 +1,65 @@
+//! EVM development RPC interface.
+use jsonrpc_core::Result;
+use jsonrpc_macros::Trailing;
+
+use parity_rpc::v1::types::{Bytes, H160, H256, U256};
+
+build_rpc_trait! {
+    /// Chain manipulation methods for testing, following the conventions
+    /// of Ganache.
+    pub trait Evm {
+        /// Takes a snapshot of the chain, including the chain clock and the
+        /// impersonated accounts, and returns its identifier. Fails if the
+        /// state history is limited.
+        #[rpc(name = "evm_snapshot")]
+        fn snapshot(&self) -> Result<U256>;
+
+        /// Reverts the chain to a snapshot. The snapshot and all snapshots
+        /// taken after it are discarded. Returns false if there is no such
+        /// snapshot.
+        #[rpc(name = "evm_revert")]
+        fn revert(&self, U256) -> Result<bool>;
+
+        /// Mines a block containing any pending transactions, or an empty
+        /// block if there are none. If a timestamp is given, it is used for
+        /// the block.
+        #[rpc(name = "evm_mine")]
+        fn mine(&self, Trailing<u64>) -> Result<U256>;
+
+        /// Moves the chain time forward by the given number of seconds.
+        /// Returns the total time adjustment, in seconds.
+        #[rpc(name = "evm_increaseTime")]
+        fn increase_time(&self, u64) -> Result<i64>;
+
+        /// Sets the timestamp of the next block. The timestamp must not be
+        /// lower than that of the latest block.
+        #[rpc(name = "evm_setNextBlockTimestamp")]
+        fn set_next_block_timestamp(&self, u64) -> Result<bool>;
+
+        /// Accepts unsigned transactions from an address through
+        /// `eth_sendTransaction`.
+        #[rpc(name = "evm_impersonateAccount")]
+        fn impersonate_account(&self, H160) -> Result<bool>;
+
+        /// Stops accepting unsigned transactions from an address. Returns
+        /// false if the address was not impersonated.
+        #[rpc(name = "evm_stopImpersonatingAccount")]
+        fn stop_impersonating_account(&self, H160) -> Result<bool>;
+
+        /// Sets the balance of an account in a new block.
+        #[rpc(name = "evm_setAccountBalance")]
+        fn set_account_balance(&self, H160, U256) -> Result<bool>;
+
+        /// Sets the nonce of an account in a new block.
+        #[rpc(name = "evm_setAccountNonce")]
+        fn set_account_nonce(&self, H160, U256) -> Result<bool>;
+
+        /// Sets the code of an account in a new block.
+        #[rpc(name = "evm_setAccountCode")]
+        fn set_account_code(&self, H160, Bytes) -> Result<bool>;
+
+        /// Sets a storage slot of an account in a new block.
+        #[rpc(name = "evm_setAccountStorageAt")]
+        fn set_account_storage_at(&self, H160, H256, H256) -> Result<bool>;
+    }
+}
@@ -1,5 +1,9 @@
 //! RPC traits for the client.
 
+pub mod debug;
+pub mod evm;
 pub mod oasis;
+pub mod oasis_dev;
+pub mod traces;
 
-pub use self::oasis::Oasis;
+pub use self::{debug::Debug, evm::Evm, oasis::Oasis, oasis_dev::OasisDev, traces::Traces};
@@ -9,9 +9,18 @@ build_rpc_trait! {
     pub trait Oasis {
         type Metadata;
         /// Returns the public key of a contract, given its address.
+        ///
+        /// If ephemeral keys are enabled, this is the contract's input key
+        /// for the epoch of the next block, which transactions should be
+        /// encrypted to until it expires.
         #[rpc(name = "oasis_getPublicKey")]
         fn public_key(&self, Address) -> BoxFuture<Option<RpcPublicKeyPayload>>;
 
+        /// Returns the public key with which the key manager signs the
+        /// public keys of contracts.
+        #[rpc(name = "oasis_getKeyManagerPublicKey")]
+        fn key_manager_public_key(&self) -> BoxFuture<Bytes>;
+
         /// Gets the expiration timestamp for a contract.
         /// The value is a Unix timestamp (seconds since the epoch).
         #[rpc(name = "oasis_getExpiry")]
@@ -34,6 +43,9 @@ pub struct RpcExecutionPayload {
     pub status_code: U64,
     /// Return value.
     pub output: Bytes,
+    /// Decoded revert reason, if the transaction reverted with one.
+    #[serde(rename = "revertReason", skip_serializing_if = "Option::is_none")]
+    pub revert_reason: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,6 +55,12 @@ pub struct RpcPublicKeyPayload {
     /// Checksum of the key manager state.
     pub checksum: Bytes,
     /// Signature from the key manager authenticating the public key,
-    /// i.e., Sign(ssk, (pk, t).
+    /// i.e., Sign(ssk, (pk, t[, epoch])), with context "EkKmPubK".
     pub signature: Bytes,
+    /// Epoch of an ephemeral key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<u64>,
+    /// Last block in which an ephemeral key is accepted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<u64>,
 }
@@ -0,0 +1,49 @@
+//! Oasis development RPC interface.
+use ethereum_types::Address;
+use jsonrpc_core::Result;
+use jsonrpc_macros::Trailing;
+
+use parity_rpc::v1::types::{BlockNumber, Bytes, H256};
+
+build_rpc_trait! {
+    /// Methods for debugging confidential contracts. These expose contract
+    /// secrets, so they must never be enabled on a public endpoint.
+    pub trait OasisDev {
+        /// Returns the keys of a contract, given its address.
+        #[rpc(name = "oasis_exportContractKey")]
+        fn export_contract_key(&self, Address) -> Result<RpcContractKey>;
+
+        /// Replaces the keys of a contract, given its address.
+        #[rpc(name = "oasis_importContractKey")]
+        fn import_contract_key(&self, Address, RpcContractKey) -> Result<bool>;
+
+        /// Returns a storage slot of a confidential contract, both encrypted
+        /// and decrypted, given the contract's address and the storage key.
+        /// Returns null if the slot is empty.
+        #[rpc(name = "oasis_getDecryptedStorage")]
+        fn decrypted_storage(&self, Address, H256, Trailing<BlockNumber>) -> Result<Option<RpcDecryptedStorage>>;
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpcContractKey {
+    /// Public input key of the contract.
+    pub public_key: Bytes,
+    /// Private input key of the contract.
+    pub private_key: Bytes,
+    /// State encryption key of the contract.
+    pub state_key: Bytes,
+    /// Checksum of the key manager state.
+    pub checksum: Bytes,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RpcDecryptedStorage {
+    /// Storage key, encrypted with the contract's state key.
+    pub encrypted_key: Bytes,
+    /// Stored value, i.e., ciphertext || tag || nonce.
+    pub ciphertext: Bytes,
+    /// Decrypted value.
+    pub plaintext: Bytes,
+}
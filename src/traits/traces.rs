@@ -0,0 +1,27 @@
+//! Traces RPC interface.
+use jsonrpc_core::Result;
+
+use parity_rpc::v1::types::{BlockNumber, Index, LocalizedTrace, TraceFilter, H256};
+
+build_rpc_trait! {
+    /// Traces of mined transactions, following the conventions of Parity.
+    ///
+    /// Traces are only available for blocks mined with tracing enabled.
+    pub trait Traces {
+        /// Returns traces matching the given filter.
+        #[rpc(name = "trace_filter")]
+        fn filter(&self, TraceFilter) -> Result<Option<Vec<LocalizedTrace>>>;
+
+        /// Returns the trace of a transaction at the given trace address.
+        #[rpc(name = "trace_get")]
+        fn trace(&self, H256, Vec<Index>) -> Result<Option<LocalizedTrace>>;
+
+        /// Returns all traces of a transaction.
+        #[rpc(name = "trace_transaction")]
+        fn transaction_traces(&self, H256) -> Result<Option<Vec<LocalizedTrace>>>;
+
+        /// Returns all traces of the transactions in a block.
+        #[rpc(name = "trace_block")]
+        fn block_traces(&self, BlockNumber) -> Result<Option<Vec<LocalizedTrace>>>;
+    }
+}
@@ -3,11 +3,17 @@ use std::{
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use ethcore::ids::BlockId;
+use ethcore::{
+    error::{CallError, ExecutionError},
+    ids::BlockId,
+    vm::Error as VmError,
+};
 use ethereum_types::U256;
 use failure::Error;
 use jsonrpc_core::{self, ErrorCode, Value};
-use parity_rpc::v1::types::BlockNumber;
+use parity_rpc::v1::{helpers::errors, types::BlockNumber};
+
+use crate::confidential::ConfidentialError;
 
 pub fn get_timestamp() -> u64 {
     SystemTime::now()
@@ -21,8 +27,10 @@ pub fn gwei_to_wei(gwei: u64) -> U256 {
 }
 
 /// Convert an RPC block number to block id.
+///
+/// There is no pending block, so "pending" maps to the latest block. State
+/// queries handle "pending" separately (see `Blockchain::state_at`).
 pub fn block_number_to_id(number: BlockNumber) -> BlockId {
-    // For "pending", just use latest block.
     match number {
         BlockNumber::Num(num) => BlockId::Number(num),
         BlockNumber::Earliest => BlockId::Earliest,
@@ -31,6 +39,36 @@ pub fn block_number_to_id(number: BlockNumber) -> BlockId {
     }
 }
 
+/// Selector of Solidity's `Error(string)`, used to encode revert reasons.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+
+/// Decode the revert reason from the output of a reverted call, if it is an
+/// ABI-encoded `Error(string)`.
+pub fn decode_revert_reason(output: &[u8]) -> Option<String> {
+    if output.len() < 4 || output[..4] != ERROR_SELECTOR {
+        return None;
+    }
+    let data = &output[4..];
+
+    // Reads a 32-byte big-endian word as a length or offset.
+    let word = |offset: usize| -> Option<usize> {
+        let word = data.get(offset..offset.checked_add(32)?)?;
+        if word[..24].iter().any(|b| *b != 0) {
+            return None;
+        }
+        let mut value = [0u8; 8];
+        value.copy_from_slice(&word[24..]);
+        Some(u64::from_be_bytes(value) as usize)
+    };
+
+    let offset = word(0)?;
+    let len = word(offset)?;
+    let start = offset.checked_add(32)?;
+    let reason = data.get(start..start.checked_add(len)?)?;
+
+    String::from_utf8(reason.to_vec()).ok()
+}
+
 /// Constructs a JSON-RPC error from a string message, with error code -32603.
 pub fn jsonrpc_error(err: Error) -> jsonrpc_core::Error {
     jsonrpc_core::Error {
@@ -40,6 +78,24 @@ pub fn jsonrpc_error(err: Error) -> jsonrpc_core::Error {
     }
 }
 
+/// Constructs a JSON-RPC error for a reverted transaction, with error code 3.
+///
+/// Following Geth, the message includes the decoded revert reason, if any,
+/// and the data holds the raw revert data.
+pub fn revert_error(output: &[u8]) -> jsonrpc_core::Error {
+    let message = match decode_revert_reason(output) {
+        Some(reason) => format!("execution reverted: {}", reason),
+        None => "execution reverted".to_string(),
+    };
+    let data: String = output.iter().map(|b| format!("{:02x}", b)).collect();
+
+    jsonrpc_core::Error {
+        code: ErrorCode::ServerError(3),
+        message,
+        data: Some(Value::String(format!("0x{}", data))),
+    }
+}
+
 /// Constructs a JSON-RPC error for a transaction execution error.
 /// TODO: format error message
 pub fn execution_error<T: fmt::Display>(data: T) -> jsonrpc_core::Error {
@@ -49,3 +105,120 @@ pub fn execution_error<T: fmt::Display>(data: T) -> jsonrpc_core::Error {
         data: Some(Value::String(format!("{}", data))),
     }
 }
+
+/// Constructs a JSON-RPC error for a failed call, giving confidential
+/// execution errors their own codes (see `ConfidentialError::code`).
+pub fn call_error(error: CallError) -> jsonrpc_core::Error {
+    let code = match error {
+        CallError::Execution(ExecutionError::Internal(ref message)) => {
+            ConfidentialError::code_of(message)
+        }
+        _ => None,
+    };
+
+    match code {
+        Some(code) => confidential_error(code, error),
+        None => errors::call(error),
+    }
+}
+
+/// Constructs a JSON-RPC error for a VM exception, giving confidential
+/// execution errors their own codes (see `ConfidentialError::code`).
+pub fn vm_error(error: &VmError, output: &[u8]) -> jsonrpc_core::Error {
+    let code = match error {
+        VmError::Confidential(message) => ConfidentialError::code_of(message),
+        _ => None,
+    };
+
+    match code {
+        Some(code) => confidential_error(code, error),
+        None => errors::vm(error, output),
+    }
+}
+
+/// Constructs a JSON-RPC error for a confidential execution error with the
+/// given code.
+fn confidential_error<T: fmt::Display>(code: i64, data: T) -> jsonrpc_core::Error {
+    jsonrpc_core::Error {
+        code: ErrorCode::ServerError(code),
+        ..execution_error(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_error(reason: &str) -> Vec<u8> {
+        let mut output = ERROR_SELECTOR.to_vec();
+        let mut word = [0u8; 32];
+        word[31] = 32;
+        output.extend_from_slice(&word);
+        word[31] = reason.len() as u8;
+        output.extend_from_slice(&word);
+        output.extend_from_slice(reason.as_bytes());
+        output.resize(output.len() + (32 - reason.len() % 32) % 32, 0);
+        output
+    }
+
+    #[test]
+    fn test_decode_revert_reason() {
+        assert_eq!(
+            decode_revert_reason(&encode_error("not enough funds")),
+            Some("not enough funds".to_string())
+        );
+        assert_eq!(
+            decode_revert_reason(&encode_error("")),
+            Some("".to_string())
+        );
+
+        // Not an Error(string).
+        assert_eq!(decode_revert_reason(&[]), None);
+        assert_eq!(decode_revert_reason(&[0xde, 0xad, 0xbe, 0xef]), None);
+
+        // Truncated.
+        let output = encode_error("not enough funds");
+        assert_eq!(decode_revert_reason(&output[..40]), None);
+        assert_eq!(decode_revert_reason(&output[..4 + 64 + 8]), None);
+    }
+
+    #[test]
+    fn test_revert_error() {
+        let err = revert_error(&encode_error("not enough funds"));
+        assert_eq!(err.code, ErrorCode::ServerError(3));
+        assert_eq!(err.message, "execution reverted: not enough funds");
+
+        let err = revert_error(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(err.message, "execution reverted");
+        assert_eq!(err.data, Some(Value::String("0xdeadbeef".to_string())));
+    }
+
+    #[test]
+    fn test_confidential_error_codes() {
+        let err = vm_error(&VmError::from(ConfidentialError::PayloadOpenFailed), &[]);
+        assert_eq!(
+            err.code,
+            ErrorCode::ServerError(ConfidentialError::PayloadOpenFailed.code())
+        );
+
+        let err = vm_error(&VmError::from(ConfidentialError::NonceExhausted), &[]);
+        assert_eq!(
+            err.code,
+            ErrorCode::ServerError(ConfidentialError::NonceExhausted.code())
+        );
+
+        let err = call_error(CallError::Execution(ExecutionError::Internal(
+            ConfidentialError::KeyManager("unreachable".to_string()).to_string(),
+        )));
+        assert_eq!(
+            err.code,
+            ErrorCode::ServerError(ConfidentialError::KeyManager(String::new()).code())
+        );
+
+        // Generic execution errors keep their code.
+        let err = execution_error(ConfidentialError::NonceExhausted);
+        assert_eq!(err.code, ErrorCode::ServerError(-32015));
+        let err = execution_error("Insufficient gas price");
+        assert_eq!(err.code, ErrorCode::ServerError(-32015));
+    }
+}